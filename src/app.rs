@@ -12,70 +12,289 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::{collections::BTreeSet, path::PathBuf};
+
 use crate::backend::{
     self,
+    balance::{self, Flow},
     building::{
-        self,
-        info::{self, Info},
+        self, info,
         types::{self, Type},
     },
-    productivity, recipe, Condition,
+    data,
+    logistics::{Route, Vehicle},
+    make_or_buy::{self, Verdict},
+    market::Town,
+    money::Money,
+    monte_carlo::{self, Noise},
+    optimizer::{self, Objective},
+    plan::Plan,
+    recipe,
+    research::{self, Unlocks},
+    sensitivity,
+    settings::{Category, Settings},
+    solver, source, timeline, wage_advisor, Condition,
 };
 use egui_extras::{Column, TableBuilder};
 use itertools::Itertools;
 
 const CROSS: &str = "🗙";
+/// 优化器显示的规划数。
+const OPTIMIZER_RESULTS: usize = 5;
+
+/// 按需计算的分析结果。规划修改后只标记为过期，点击按钮时才重新计算。
+struct Analysis<T> {
+    result: Option<Result<T, String>>,
+    stale: bool,
+}
+
+impl<T> Default for Analysis<T> {
+    fn default() -> Self {
+        Analysis {
+            result: None,
+            stale: false,
+        }
+    }
+}
+
+impl<T> Analysis<T> {
+    fn invalidate(&mut self) {
+        self.stale = self.result.is_some();
+    }
+
+    /// 显示计算按钮和状态，出错时显示错误。已有结果时返回它，过期的结果也会返回。
+    fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        compute: impl FnOnce() -> backend::Result<T>,
+    ) -> Option<&T> {
+        ui.horizontal(|ui| {
+            let text = if self.result.is_some() {
+                "Recompute"
+            } else {
+                "Compute"
+            };
+            if ui.button(text).clicked() {
+                self.result = Some(compute().map_err(|e| e.to_string()));
+                self.stale = false;
+            }
+            if self.stale {
+                ui.colored_label(egui::Color32::YELLOW, "out of date");
+            }
+        });
+        match &self.result {
+            None => None,
+            Some(Err(e)) => {
+                ui.colored_label(egui::Color32::RED, e);
+                None
+            }
+            Some(Ok(value)) => Some(value),
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct App {
-    simulation_conditions: Vec<backend::Condition>,
+    plan: Plan,
+    plan_path: Option<PathBuf>,
+    path_input: String,
     simulation_report: Option<backend::Report>,
+    simulation_error: Option<String>,
+    timeline: Analysis<timeline::Timeline>,
+    timeline_options: timeline::Options,
+    simulation_options: backend::Options,
+    sensitivity_range: f64,
+    monte_carlo: Analysis<monte_carlo::Outcome>,
+    monte_carlo_config: monte_carlo::Config,
+    optimizer: optimizer::Constraints,
+    wage_advice: Analysis<Vec<wage_advisor::Advice>>,
+    make_or_buy: Analysis<Vec<make_or_buy::Row>>,
+    make_or_buy_config: make_or_buy::Config,
+    optimizer_plan_types_only: bool,
+    optimizer_solutions: Vec<optimizer::Solution>,
+    condition_errors: Vec<Option<String>>,
+    data_watcher: Option<data::Watcher>,
+    error: Option<String>,
+    chain_target: Option<recipe::Item>,
+    chain_rate: f64,
+    source_wage: building::WorkerWage,
 }
 
 impl App {
-    pub fn new(_: &eframe::CreationContext) -> Self {
-        Self::default()
+    pub fn new(_: &eframe::CreationContext, data_watcher: Option<data::Watcher>) -> Self {
+        Self {
+            data_watcher,
+            chain_rate: 10.0,
+            sensitivity_range: sensitivity::DEFAULT_RANGE * 100.0,
+            monte_carlo_config: monte_carlo::Config::default(),
+            ..Default::default()
+        }
     }
 
-    pub fn simulate(&mut self) {
-        let simulator = backend::Simulator::from_conditions(&self.simulation_conditions);
-        if let Ok(sim) = simulator {
-            self.simulation_report = Some(sim.simulate());
-        } else {
-            unreachable!();
+    /// 数据文件有修改时重新加载并重新模拟。
+    fn reload_data(&mut self, ctx: &egui::Context) {
+        let Some(watcher) = &mut self.data_watcher else {
+            return;
+        };
+        match watcher.poll() {
+            Some(Ok(())) => {
+                self.error = None;
+                self.simulate();
+            }
+            Some(Err(e)) => self.error = Some(format!("data reload failed: {}", e)),
+            None => {}
         }
+        ctx.request_repaint_after(data::Watcher::INTERVAL);
     }
 
-    pub fn add_collector(&mut self, building_type: types::Collector) {
-        let recipe_id = building::info::get_collector_info(building_type).recipes[0].clone();
-        self.simulation_conditions.push(Condition::Collector {
+    /// 重新模拟。出错的条件不会导致崩溃，错误信息显示在对应的条件行上。
+    /// 耗时的分析不在这里重新计算，只标记为过期。
+    pub fn simulate(&mut self) {
+        self.condition_errors = self
+            .plan
+            .conditions
+            .iter()
+            .map(|cond| match cond.check() {
+                Err(e) => Some(e.to_string()),
+                Ok(()) => {
+                    let missing = self.unlocks().missing(cond);
+                    (!missing.is_empty()).then(|| format!("needs research: {}", missing.join(", ")))
+                }
+            })
+            .collect();
+        let report = backend::Simulator::for_plan(&self.plan)
+            .and_then(|sim| sim.with_options(self.simulation_options).simulate());
+        self.simulation_error = report.as_ref().err().map(|e| e.to_string());
+        self.simulation_report = report.ok();
+        self.timeline.invalidate();
+        self.monte_carlo.invalidate();
+        self.wage_advice.invalidate();
+        self.make_or_buy.invalidate();
+    }
+
+    /// 规划已研究的节点所允许的配方和建筑。
+    fn unlocks(&self) -> Unlocks<'_> {
+        Unlocks::new(self.plan.researched.as_ref())
+    }
+
+    /// 新加入的建筑默认使用第一个已解锁的配方。
+    fn first_recipe(&self, building_type: Type) -> backend::Result<recipe::Id> {
+        let unlocks = self.unlocks();
+        let recipe_id = info::get(building_type)?
+            .recipes()
+            .iter()
+            .find(|id| unlocks.recipe(id))
+            .cloned()
+            .ok_or(building::Error::NoRecipe(building_type))?;
+        Ok(recipe_id)
+    }
+
+    pub fn add_collector(&mut self, building_type: types::Collector) -> backend::Result<()> {
+        let recipe_id = self.first_recipe(Type::Collector(building_type))?;
+        self.plan.conditions.push(Condition::Collector {
             building_type,
             recipe_id,
-            worker_wage: building::WorkerWage::Percent100,
+            worker_wage: building::WorkerWage::PERCENT_100,
             collector_amount: building::OutbuildingAmount::Five,
             amount: 1,
+            location: None,
         });
+        Ok(())
     }
 
-    pub fn add_farm(&mut self, building_type: types::Farm) {
-        let recipe_id = building::info::get_farm_info(building_type).recipes[0].clone();
-        self.simulation_conditions.push(Condition::Farm {
+    pub fn add_farm(&mut self, building_type: types::Farm) -> backend::Result<()> {
+        let recipe_id = self.first_recipe(Type::Farm(building_type))?;
+        self.plan.conditions.push(Condition::Farm {
             building_type,
             recipe_id,
-            worker_wage: building::WorkerWage::Percent100,
+            worker_wage: building::WorkerWage::PERCENT_100,
             field_amount: building::OutbuildingAmount::Five,
             amount: 1,
-        })
+            location: None,
+        });
+        Ok(())
     }
 
-    pub fn add_factory(&mut self, building_type: types::Factory) {
-        let recipe_id = building::info::get_factory_info(building_type).recipes[0].clone();
-        self.simulation_conditions.push(Condition::Factory {
+    pub fn add_factory(&mut self, building_type: types::Factory) -> backend::Result<()> {
+        let recipe_id = self.first_recipe(Type::Factory(building_type))?;
+        self.plan.conditions.push(Condition::Factory {
             building_type,
             recipe_id,
-            worker_wage: building::WorkerWage::Percent100,
+            worker_wage: building::WorkerWage::PERCENT_100,
             amount: 1,
-        })
+            location: None,
+        });
+        Ok(())
+    }
+
+    pub fn add_depot(&mut self, building_type: types::Depot) -> backend::Result<()> {
+        info::get_depot_info(building_type)?;
+        self.plan.conditions.push(Condition::Depot {
+            building_type,
+            amount: 1,
+            location: None,
+        });
+        Ok(())
+    }
+
+    pub fn open_plan(&mut self, path: PathBuf) -> backend::Result<()> {
+        self.plan = Plan::load(&path)?;
+        self.path_input = path.display().to_string();
+        self.plan_path = Some(path);
+        self.simulate();
+        Ok(())
+    }
+
+    /// 保存到当前规划文件；尚未保存过时，保存到路径输入框中的路径。
+    pub fn save_plan(&mut self) -> backend::Result<()> {
+        match self.plan_path.clone() {
+            Some(path) => self.plan.save(&path),
+            None => self.save_plan_as(PathBuf::from(&self.path_input)),
+        }
+    }
+
+    pub fn save_plan_as(&mut self, path: PathBuf) -> backend::Result<()> {
+        self.plan.save(&path)?;
+        self.path_input = path.display().to_string();
+        self.plan_path = Some(path);
+        Ok(())
+    }
+
+    /// 将生产所选物品的整条生产链加入规划。
+    pub fn build_chain(&mut self) -> backend::Result<()> {
+        let Some(target) = self.chain_target else {
+            return Ok(());
+        };
+        let conditions =
+            solver::solve(target, self.chain_rate, &self.plan.settings, self.unlocks())?;
+        self.plan.conditions.extend(conditions);
+        Ok(())
+    }
+
+    /// 按当前约束搜索最好的规划。勾选只用规划中已有的建筑类型时，以此限制搜索范围。
+    pub fn optimize(&mut self) -> backend::Result<()> {
+        let allowed = self.optimizer_plan_types_only.then(|| {
+            self.plan
+                .conditions
+                .iter()
+                .map(|c| c.building_type())
+                .unique()
+                .collect()
+        });
+        let constraints = optimizer::Constraints {
+            allowed,
+            researched: self.plan.researched.clone(),
+            settings: self.plan.settings.clone(),
+            market: self.plan.market.clone(),
+            ..self.optimizer.clone()
+        };
+        self.optimizer_solutions = optimizer::optimize(&constraints, OPTIMIZER_RESULTS)?;
+        Ok(())
+    }
+
+    /// 记录操作的结果，出错时显示错误信息。
+    fn report_result(&mut self, result: backend::Result<()>) {
+        self.error = result.err().map(|e| e.to_string());
     }
 }
 
@@ -129,6 +348,8 @@ trait View {
 pub struct ProductivityView {
     mark_as_delete: Option<usize>,
     condition_changed: bool,
+    /// 规划已研究的节点，配方下拉框只列出已解锁的配方。
+    researched: Option<BTreeSet<String>>,
 }
 
 impl ProductivityView {
@@ -136,6 +357,7 @@ impl ProductivityView {
         ProductivityView {
             mark_as_delete: None,
             condition_changed: false,
+            researched: None,
         }
     }
     fn show_body_content(
@@ -143,57 +365,104 @@ impl ProductivityView {
         body: &mut egui_extras::TableBody,
         i: usize,
         cond: &mut backend::Condition,
+        contribution: Option<&backend::Contribution>,
+        error: Option<&str>,
     ) {
-        body.row(25.0, |row| match cond {
-            backend::Condition::Collector {
-                building_type,
-                recipe_id,
-                worker_wage,
-                collector_amount,
-                amount,
-            } => {
-                self.show_collector_cond(
-                    i,
-                    row,
+        body.row(25.0, |mut row| {
+            match cond {
+                backend::Condition::Collector {
                     building_type,
                     recipe_id,
                     worker_wage,
                     collector_amount,
                     amount,
-                );
-            }
-            backend::Condition::Farm {
-                building_type,
-                recipe_id,
-                worker_wage,
-                field_amount,
-                amount,
-            } => {
-                self.show_farm_cond(
-                    i,
-                    row,
+                    ..
+                } => {
+                    self.show_collector_cond(
+                        i,
+                        &mut row,
+                        building_type,
+                        recipe_id,
+                        worker_wage,
+                        collector_amount,
+                        amount,
+                    );
+                }
+                backend::Condition::Farm {
                     building_type,
                     recipe_id,
                     worker_wage,
                     field_amount,
                     amount,
-                );
-            }
-            backend::Condition::Factory {
-                building_type,
-                recipe_id,
-                worker_wage,
-                amount,
-            } => {
-                self.show_factory_cond(i, row, building_type, recipe_id, worker_wage, amount);
+                    ..
+                } => {
+                    self.show_farm_cond(
+                        i,
+                        &mut row,
+                        building_type,
+                        recipe_id,
+                        worker_wage,
+                        field_amount,
+                        amount,
+                    );
+                }
+                backend::Condition::Factory {
+                    building_type,
+                    recipe_id,
+                    worker_wage,
+                    amount,
+                    ..
+                } => {
+                    self.show_factory_cond(
+                        i,
+                        &mut row,
+                        building_type,
+                        recipe_id,
+                        worker_wage,
+                        amount,
+                    );
+                }
+                backend::Condition::Depot {
+                    building_type,
+                    amount,
+                    ..
+                } => {
+                    self.show_depot_cond(&mut row, building_type, amount);
+                }
             }
+            row.col(|ui| {
+                self.show_location(ui, cond);
+            });
+            row.col(|ui| {
+                if let Some(contribution) = contribution {
+                    let profit = contribution.profit();
+                    let color = if profit < Money::zero() {
+                        egui::Color32::RED
+                    } else {
+                        egui::Color32::DARK_GREEN
+                    };
+                    ui.colored_label(color, profit.to_string());
+                    if contribution.utilization() < 1.0 {
+                        ui.label(format!("({:.0}% busy)", contribution.utilization() * 100.0));
+                    }
+                }
+            });
+            row.col(|ui| {
+                self.show_close_button(ui, i);
+            });
+            row.col(|ui| {
+                if let Some(e) = error {
+                    ui.colored_label(egui::Color32::RED, e);
+                }
+            });
         });
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn show_collector_cond(
         &mut self,
         i: usize,
-        mut row: egui_extras::TableRow,
+        row: &mut egui_extras::TableRow,
         building_type: &mut types::Collector,
         recipe_id: &mut recipe::Id,
         worker_wage: &mut building::WorkerWage,
@@ -207,10 +476,10 @@ impl ProductivityView {
             ui.label(format!("{:?}", building_type));
         });
         row.col(|ui| {
-            self.show_recipe_combobox(ui, info::get(Type::Collector(*building_type)), recipe_id, i);
+            self.show_recipe_combobox(ui, Type::Collector(*building_type), recipe_id, i);
         });
         row.col(|ui| {
-            self.show_worker_wage_combobox(ui, worker_wage, i);
+            self.show_worker_wage_slider(ui, worker_wage, i);
         });
         row.col(|ui| {
             self.show_outbuilding_amount_combobox(ui, collector_amount, i);
@@ -218,15 +487,13 @@ impl ProductivityView {
         row.col(|ui| {
             self.show_amount(ui, amount);
         });
-        row.col(|ui| {
-            self.show_close_button(ui, i);
-        });
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn show_farm_cond(
         &mut self,
         i: usize,
-        mut row: egui_extras::TableRow,
+        row: &mut egui_extras::TableRow,
         building_type: &mut types::Farm,
         recipe_id: &mut recipe::Id,
         worker_wage: &mut building::WorkerWage,
@@ -240,10 +507,10 @@ impl ProductivityView {
             ui.label(format!("{:?}", building_type));
         });
         row.col(|ui| {
-            self.show_recipe_combobox(ui, info::get(Type::Farm(*building_type)), recipe_id, i);
+            self.show_recipe_combobox(ui, Type::Farm(*building_type), recipe_id, i);
         });
         row.col(|ui| {
-            self.show_worker_wage_combobox(ui, worker_wage, i);
+            self.show_worker_wage_slider(ui, worker_wage, i);
         });
         row.col(|ui| {
             self.show_outbuilding_amount_combobox(ui, field_amount, i);
@@ -251,15 +518,12 @@ impl ProductivityView {
         row.col(|ui| {
             self.show_amount(ui, amount);
         });
-        row.col(|ui| {
-            self.show_close_button(ui, i);
-        });
     }
 
     fn show_factory_cond(
         &mut self,
         i: usize,
-        mut row: egui_extras::TableRow,
+        row: &mut egui_extras::TableRow,
         building_type: &mut types::Factory,
         recipe_id: &mut recipe::Id,
         worker_wage: &mut building::WorkerWage,
@@ -272,10 +536,10 @@ impl ProductivityView {
             ui.label(format!("{:?}", building_type));
         });
         row.col(|ui| {
-            self.show_recipe_combobox(ui, info::get(Type::Factory(*building_type)), recipe_id, i);
+            self.show_recipe_combobox(ui, Type::Factory(*building_type), recipe_id, i);
         });
         row.col(|ui| {
-            self.show_worker_wage_combobox(ui, worker_wage, i);
+            self.show_worker_wage_slider(ui, worker_wage, i);
         });
         row.col(|ui| {
             ui.label("N/A");
@@ -283,8 +547,33 @@ impl ProductivityView {
         row.col(|ui| {
             self.show_amount(ui, amount);
         });
+    }
+
+    fn show_depot_cond(
+        &mut self,
+        row: &mut egui_extras::TableRow,
+        building_type: &mut types::Depot,
+        amount: &mut u8,
+    ) {
+        row.col(|ui| {
+            ui.label("Depot");
+        });
+        row.col(|ui| {
+            ui.label(format!("{:?}", building_type));
+        });
+        row.col(|ui| {
+            if let Ok(info) = info::get_depot_info(*building_type) {
+                ui.label(format!("capacity {}", info.capacity));
+            }
+        });
+        row.col(|ui| {
+            ui.label("N/A");
+        });
+        row.col(|ui| {
+            ui.label("N/A");
+        });
         row.col(|ui| {
-            self.show_close_button(ui, i);
+            self.show_amount(ui, amount);
         });
     }
 
@@ -295,6 +584,19 @@ impl ProductivityView {
         }
     }
 
+    fn show_location(&mut self, ui: &mut egui::Ui, cond: &mut backend::Condition) {
+        let mut text = cond.location().unwrap_or_default().to_owned();
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut text)
+                .hint_text("none")
+                .desired_width(80.0),
+        );
+        if response.changed() {
+            cond.set_location(Some(text).filter(|text| !text.is_empty()));
+            self.condition_changed = true;
+        }
+    }
+
     fn show_close_button(&mut self, ui: &mut egui::Ui, i: usize) {
         if ui.button(CROSS).clicked() {
             self.mark_as_delete = Some(i);
@@ -302,67 +604,552 @@ impl ProductivityView {
         }
     }
 
-    fn show_worker_wage_combobox(
+    fn show_worker_wage_slider(
         &mut self,
         ui: &mut egui::Ui,
         worker_wage: &mut building::WorkerWage,
         idx: usize,
     ) {
-        eframe::egui::ComboBox::from_id_source(format!("worker_wage:{}", idx))
-            .selected_text(format!("{}", worker_wage))
-            .show_ui(ui, |ui| {
-                for wage in enum_iterator::all::<building::WorkerWage>() {
-                    let response = ui.selectable_value(worker_wage, wage, format!("{}", wage));
-                    if response.clicked() {
-                        self.condition_changed = true;
+        if Self::show_wage_slider(ui, worker_wage, format!("worker_wage:{}", idx)) {
+            self.condition_changed = true;
+        }
+    }
+
+    /// 工资滑块，旁边的菜单可以快速选择预设档位。工资有变化时返回 `true`。
+    fn show_wage_slider(
+        ui: &mut egui::Ui,
+        worker_wage: &mut building::WorkerWage,
+        id: impl std::hash::Hash,
+    ) -> bool {
+        let wages = &data::current().wages;
+        let mut percent = worker_wage.percent();
+        ui.push_id(id, |ui| {
+            ui.horizontal(|ui| {
+                ui.add(egui::Slider::new(&mut percent, wages.min..=wages.max).suffix("%"));
+                ui.menu_button("▾", |ui| {
+                    for wage in building::WorkerWage::PRESETS {
+                        if ui.button(wage.to_string()).clicked() {
+                            percent = wage.percent();
+                            ui.close_menu();
+                        }
                     }
-                }
+                });
             });
+        });
+        let changed = percent != worker_wage.percent();
+        *worker_wage = building::WorkerWage::new(percent);
+        changed
     }
 
     fn show_recipe_combobox(
         &mut self,
         ui: &mut egui::Ui,
-        info: &Info,
+        building_type: Type,
         recipe_id: &mut recipe::Id,
         idx: usize,
     ) {
+        let info = match info::get(building_type) {
+            Ok(info) => info,
+            Err(e) => {
+                ui.colored_label(egui::Color32::RED, e.to_string());
+                return;
+            }
+        };
         eframe::egui::ComboBox::from_id_source(format!("recipe:{}", idx))
             .selected_text(recipe_id.to_string())
-            .show_ui(ui, |ui| match info {
-                Info::Collector(info) => {
-                    for id in &info.recipes {
-                        let response = ui
-                            .selectable_label(recipe_id == id, id.to_string())
-                            .clicked();
-                        if response {
-                            *recipe_id = id.clone();
-                            self.condition_changed = true;
-                        }
+            .show_ui(ui, |ui| {
+                let unlocks = Unlocks::new(self.researched.as_ref());
+                for id in info.recipes() {
+                    if id != recipe_id && !unlocks.recipe(id) {
+                        continue;
+                    }
+                    let response = ui
+                        .selectable_label(recipe_id == id, id.to_string())
+                        .clicked();
+                    if response {
+                        *recipe_id = id.clone();
+                        self.condition_changed = true;
                     }
                 }
-                Info::Farm(info) => {
-                    for id in &info.recipes {
-                        let response = ui
-                            .selectable_label(recipe_id == id, id.to_string())
-                            .clicked();
-                        if response {
-                            *recipe_id = id.clone();
-                            self.condition_changed = true;
-                        }
+            });
+    }
+
+    fn show_balance(ui: &mut egui::Ui, balances: &[balance::ItemBalance]) {
+        for (flow, title) in [
+            (Flow::Surplus, "Surplus (sold)"),
+            (Flow::Deficit, "Deficit (bought)"),
+            (Flow::Balanced, "Balanced (internal)"),
+        ] {
+            let mut group = balances.iter().filter(|b| b.flow == flow).peekable();
+            if group.peek().is_none() {
+                continue;
+            }
+            ui.strong(title);
+            for b in group {
+                let text = match flow {
+                    Flow::Surplus if b.unsold > 0.0 => format!(
+                        "{:?}: +{:.2} per month ({:.2} made, {:.2} used), sells {}, {:.2} unsold",
+                        b.item, b.sold, b.produced, b.consumed, b.sales, b.unsold
+                    ),
+                    Flow::Surplus => format!(
+                        "{:?}: +{:.2} per month ({:.2} made, {:.2} used), sells {}",
+                        b.item, b.sold, b.produced, b.consumed, b.sales
+                    ),
+                    Flow::Deficit => format!(
+                        "{:?}: -{:.2} per month, {:.0}% self-supplied, buys {}",
+                        b.item,
+                        b.bought,
+                        b.self_supplied_ratio().unwrap_or(0.0) * 100.0,
+                        b.purchase_cost
+                    ),
+                    Flow::Balanced => format!(
+                        "{:?}: {:.2} per month internal, worth {}",
+                        b.item, b.self_supplied, b.internal_value
+                    ),
+                };
+                ui.label(text);
+            }
+            ui.separator();
+        }
+    }
+
+    /// 各物品卖给各城镇的数量和收入。
+    fn show_sales(ui: &mut egui::Ui, report: &backend::Report) {
+        egui::Grid::new("market_sales")
+            .striped(true)
+            .show(ui, |ui| {
+                ui.strong("item");
+                ui.strong("town");
+                ui.strong("sold");
+                ui.strong("revenue");
+                ui.end_row();
+                for sale in report.sales() {
+                    for town in sale.towns.iter() {
+                        ui.label(format!("{:?}", sale.item));
+                        ui.label(&town.town);
+                        ui.label(format!("{:.2}", town.sold));
+                        ui.label(Money::from(town.revenue.round() as i64).to_string());
+                        ui.end_row();
+                    }
+                    if sale.unsold() > 0.0 {
+                        ui.label(format!("{:?}", sale.item));
+                        ui.colored_label(egui::Color32::RED, "unsold");
+                        ui.colored_label(egui::Color32::RED, format!("{:.2}", sale.unsold()));
+                        ui.label("");
+                        ui.end_row();
                     }
                 }
-                Info::Factory(info) => {
-                    for id in &info.recipes {
-                        let response = ui
-                            .selectable_label(recipe_id == id, id.to_string())
-                            .clicked();
-                        if response {
-                            *recipe_id = id.clone();
-                            self.condition_changed = true;
-                        }
+            });
+    }
+
+    fn show_contributions(ui: &mut egui::Ui, report: &backend::Report) {
+        let ratio = |part: Money, whole: Money| {
+            if whole.value() == 0 {
+                0.0
+            } else {
+                part.value() as f64 / whole.value() as f64 * 100.0
+            }
+        };
+        for (i, contribution) in report.contributions().iter().enumerate() {
+            ui.label(format!(
+                "  #{}: {:.1}% busy, price {}, upkeep {}, {:.1}% of sales, {:.1}% of material cost, internal {}, logistics {}",
+                i + 1,
+                contribution.utilization() * 100.0,
+                contribution.price(),
+                contribution.upkeep(),
+                ratio(contribution.sales(), report.monthly_sales()),
+                ratio(contribution.material_cost(), report.monthly_material_cost()),
+                contribution.internal(),
+                contribution.logistics(),
+            ));
+            let mut flows = contribution
+                .productivity()
+                .iter()
+                .map(|(item, speed)| {
+                    format!("{:?} {:+.2}", item, speed.per_month(report.month_length()))
+                })
+                .collect::<Vec<_>>();
+            flows.sort();
+            ui.label(format!("      {}", flows.join(", ")));
+        }
+    }
+
+    fn show_sensitivity(app: &mut App, ui: &mut egui::Ui) {
+        ui.add(
+            egui::DragValue::new(&mut app.sensitivity_range)
+                .clamp_range(1.0..=100.0)
+                .prefix("price ±")
+                .suffix("%"),
+        );
+        let Some(report) = &app.simulation_report else {
+            return;
+        };
+        let result = sensitivity::changes(app.sensitivity_range / 100.0, 1)
+            .and_then(|changes| sensitivity::sensitivity(report, None, &changes));
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                ui.colored_label(egui::Color32::RED, e.to_string());
+                return;
+            }
+        };
+        let base = report.monthly_profit();
+        egui::Grid::new("sensitivity").striped(true).show(ui, |ui| {
+            ui.strong("item");
+            ui.strong("price low");
+            ui.strong("price high");
+            ui.strong("swing");
+            ui.end_row();
+            for s in result.iter() {
+                let (Some(low), Some(high)) = (s.points.first(), s.points.last()) else {
+                    continue;
+                };
+                ui.label(format!("{:?}", s.item));
+                ui.label(low.monthly_profit.to_string());
+                ui.label(high.monthly_profit.to_string());
+                ui.label(s.swing().to_string());
+                ui.end_row();
+            }
+        });
+        // 龙卷风图：影响最大的物品在最上面
+        let mut bars = vec![];
+        for (i, s) in result.iter().enumerate() {
+            let y = (result.len() - i) as f64;
+            let name = format!("{:?}", s.item);
+            for (point, color) in [
+                (s.points.first(), egui::Color32::LIGHT_RED),
+                (s.points.last(), egui::Color32::LIGHT_GREEN),
+            ] {
+                let Some(point) = point else { continue };
+                let delta = (point.monthly_profit - base).value() as f64;
+                bars.push(
+                    egui::plot::Bar::new(y, delta)
+                        .name(format!("{} {:+.0}%", name, point.change * 100.0))
+                        .fill(color),
+                );
+            }
+        }
+        egui::plot::Plot::new("sensitivity_tornado")
+            .height(result.len() as f32 * 20.0 + 40.0)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .show_y(false)
+            .show(ui, |plot_ui| {
+                plot_ui.bar_chart(egui::plot::BarChart::new(bars).horizontal());
+            });
+    }
+
+    fn show_wage_advice(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        let (plan, options) = (&app.plan, app.simulation_options);
+        let Some(advice) = app
+            .wage_advice
+            .show(ui, || wage_advisor::advise(plan, options))
+            .cloned()
+        else {
+            return;
+        };
+        let changes = advice
+            .iter()
+            .filter(|advice| advice.changes())
+            .collect::<Vec<_>>();
+        if changes.is_empty() {
+            ui.label("Every row already has its most profitable wage.");
+            return;
+        }
+        egui::Grid::new("wage_advice").striped(true).show(ui, |ui| {
+            ui.strong("row");
+            ui.strong("wage");
+            ui.strong("row profit");
+            ui.strong("plan gain");
+            ui.end_row();
+            for advice in changes.iter() {
+                let name = app
+                    .plan
+                    .conditions
+                    .get(advice.index)
+                    .map(|cond| format!("{:?}", cond.building_type()))
+                    .unwrap_or_default();
+                ui.label(format!("{}. {}", advice.index + 1, name));
+                ui.label(format!("{} → {}", advice.current, advice.best));
+                ui.label(format!(
+                    "{} → {}",
+                    advice.current_profit, advice.best_profit
+                ));
+                ui.label(format!("+{}", advice.gain));
+                ui.end_row();
+            }
+        });
+        if ui.button("Apply all").clicked() {
+            wage_advisor::apply(&mut app.plan.conditions, &advice);
+            self.condition_changed = true;
+        }
+    }
+
+    /// 规划消耗的各物品自制和外购的单位成本。建议自制的物品可以一键加入生产建筑。
+    fn show_make_or_buy(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("amortize buildings over");
+            if ui
+                .add(
+                    egui::DragValue::new(&mut app.make_or_buy_config.horizon)
+                        .clamp_range(1.0..=240.0)
+                        .suffix(" months"),
+                )
+                .changed()
+            {
+                app.make_or_buy.invalidate();
+            }
+        });
+        let (plan, config) = (&app.plan, app.make_or_buy_config);
+        let Some(rows) = app
+            .make_or_buy
+            .show(ui, || make_or_buy::analyze(plan, config))
+            .cloned()
+        else {
+            return;
+        };
+        if rows.is_empty() {
+            ui.label("The plan consumes no items.");
+            return;
+        }
+        let cost = |x: Option<f64>| x.map_or(String::from("-"), |x| format!("{:.2}", x));
+        let mut build = None;
+        egui::Grid::new("make_or_buy").striped(true).show(ui, |ui| {
+            ui.strong("item");
+            ui.strong("used");
+            ui.strong("buy");
+            ui.strong("make");
+            ui.strong("verdict");
+            ui.strong("saving");
+            ui.end_row();
+            for row in rows.iter() {
+                ui.label(format!("{:?}", row.item));
+                ui.label(format!("{:.2} ({:.2} made)", row.consumed, row.produced));
+                ui.label(cost(row.buy));
+                let label = ui.label(cost(row.make.as_ref().map(|make| make.unit_cost())));
+                if let Some(make) = &row.make {
+                    label.on_hover_text(format!(
+                        "{:?} {}\ncapital {:.2}, upkeep {:.2}, inputs {:.2}, byproducts -{:.2}",
+                        make.building_type,
+                        make.recipe_id,
+                        make.capital,
+                        make.upkeep,
+                        make.inputs,
+                        make.byproducts
+                    ));
+                }
+                match row.verdict() {
+                    Verdict::Make => {
+                        ui.colored_label(egui::Color32::GREEN, "make");
+                    }
+                    Verdict::Buy => {
+                        ui.label("buy");
+                    }
+                    Verdict::Unavailable => {
+                        ui.colored_label(egui::Color32::RED, "unavailable");
+                    }
+                }
+                ui.label(cost(row.monthly_saving()));
+                let missing = row.consumed - row.produced;
+                if let (Verdict::Make, Some(make)) = (row.verdict(), &row.make) {
+                    if missing > 0.0 && ui.button("Build").clicked() {
+                        build = Some(make.condition(make.amount_for(missing)));
                     }
                 }
+                ui.end_row();
+            }
+        });
+        if let Some(cond) = build {
+            app.plan.conditions.push(cond);
+            self.condition_changed = true;
+        }
+    }
+
+    /// 编辑一种随机波动，返回是否有修改。
+    fn show_noise(ui: &mut egui::Ui, label: &str, noise: &mut Noise) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label(label);
+            let (mut kind, mut percent) = match *noise {
+                Noise::None => (0, 10.0),
+                Noise::Uniform(x) => (1, x * 100.0),
+                Noise::Normal(x) => (2, x * 100.0),
+            };
+            const KINDS: [&str; 3] = ["none", "uniform ±", "normal σ"];
+            egui::ComboBox::from_id_source(label)
+                .selected_text(KINDS[kind])
+                .show_ui(ui, |ui| {
+                    for (i, text) in KINDS.iter().enumerate() {
+                        changed |= ui.selectable_value(&mut kind, i, *text).changed();
+                    }
+                });
+            if kind != 0 {
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut percent)
+                            .clamp_range(0.0..=100.0)
+                            .suffix("%"),
+                    )
+                    .changed();
+            }
+            if changed {
+                *noise = match kind {
+                    1 => Noise::Uniform(percent / 100.0),
+                    2 => Noise::Normal(percent / 100.0),
+                    _ => Noise::None,
+                };
+            }
+        });
+        changed
+    }
+
+    fn show_monte_carlo(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        let config = &mut app.monte_carlo_config;
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut config.runs)
+                        .clamp_range(1..=100_000)
+                        .suffix(" runs"),
+                )
+                .changed();
+            changed |= ui
+                .add(egui::DragValue::new(&mut config.seed).prefix("seed "))
+                .changed();
+        });
+        changed |= Self::show_noise(ui, "prices", &mut config.price);
+        changed |= Self::show_noise(ui, "speed", &mut config.speed);
+        if changed {
+            app.monte_carlo.invalidate();
+        }
+        let (plan, options, config) = (&app.plan, app.simulation_options, &app.monte_carlo_config);
+        let Some(outcome) = app.monte_carlo.show(ui, || {
+            let report = backend::Simulator::for_plan(plan)?
+                .with_options(options)
+                .simulate()?;
+            monte_carlo::run(&report, config)
+        }) else {
+            return;
+        };
+        ui.label(format!("mean monthly profit: {}", outcome.mean_profit()));
+        ui.label(format!(
+            "5% / 50% / 95%: {} / {} / {}",
+            outcome.profit_percentile(0.05),
+            outcome.profit_percentile(0.5),
+            outcome.profit_percentile(0.95)
+        ));
+        ui.label(format!(
+            "probability of loss: {:.1}%",
+            outcome.loss_probability() * 100.0
+        ));
+        let payback = |p: f64| {
+            outcome
+                .payback_percentile(p)
+                .map_or(String::from("never"), |months| format!("{:.1}", months))
+        };
+        ui.label(format!(
+            "payback months 5% / 50% / 95%: {} / {} / {}",
+            payback(0.05),
+            payback(0.5),
+            payback(0.95)
+        ));
+        // 月利润的直方图
+        const BINS: usize = 30;
+        let profits = outcome.profits();
+        let (Some(min), Some(max)) = (profits.first(), profits.last()) else {
+            return;
+        };
+        let width = ((max - min) / BINS as f64).max(1.0);
+        let mut counts = [0u32; BINS];
+        for profit in profits {
+            counts[(((profit - min) / width) as usize).min(BINS - 1)] += 1;
+        }
+        let bars = counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                egui::plot::Bar::new(min + width * (i as f64 + 0.5), *count as f64).width(width)
+            })
+            .collect();
+        egui::plot::Plot::new("monte_carlo")
+            .height(150.0)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .show(ui, |plot_ui| {
+                plot_ui.bar_chart(egui::plot::BarChart::new(bars));
+                plot_ui.vline(egui::plot::VLine::new(0.0));
+            });
+    }
+
+    fn show_timeline(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let days = ui.add(
+                egui::DragValue::new(&mut app.timeline_options.days)
+                    .clamp_range(1..=timeline::MAX_DAYS)
+                    .suffix(" days"),
+            );
+            let buy = ui.checkbox(&mut app.timeline_options.buy_inputs, "buy missing inputs");
+            if days.changed() || buy.changed() {
+                app.timeline.invalidate();
+            }
+        });
+        let (plan, options) = (&app.plan, app.timeline_options);
+        let Some(timeline) = app.timeline.show(ui, || {
+            timeline::run(&plan.conditions, &plan.settings, options)
+        }) else {
+            return;
+        };
+        match timeline.break_even_day() {
+            Some(day) => ui.strong(format!("break even on day {}", day)),
+            None => ui.strong("does not break even in this period"),
+        };
+        for (i, utilization) in timeline.utilization().iter().enumerate() {
+            ui.label(format!("  #{}: {:.1}% busy", i + 1, utilization * 100.0));
+        }
+        let cash = timeline
+            .days()
+            .iter()
+            .map(|day| [day.day as f64, day.cash.value() as f64])
+            .collect::<egui::plot::PlotPoints>();
+        ui.label("cash:");
+        egui::plot::Plot::new("timeline_cash")
+            .height(150.0)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .show(ui, |plot_ui| {
+                plot_ui.hline(egui::plot::HLine::new(0.0));
+                plot_ui.line(egui::plot::Line::new(cash));
+            });
+        let starved = timeline
+            .days()
+            .iter()
+            .map(|day| [day.day as f64, day.starved as f64])
+            .collect::<egui::plot::PlotPoints>();
+        let items = timeline
+            .days()
+            .iter()
+            .flat_map(|day| day.inventory.keys().copied())
+            .collect::<std::collections::BTreeSet<_>>();
+        ui.label("inventory:");
+        egui::plot::Plot::new("timeline_inventory")
+            .height(200.0)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .legend(egui::plot::Legend::default())
+            .show(ui, |plot_ui| {
+                for item in items {
+                    let points = timeline
+                        .days()
+                        .iter()
+                        .map(|day| {
+                            let amount = day.inventory.get(&item).copied().unwrap_or(0);
+                            [day.day as f64, amount as f64]
+                        })
+                        .collect::<egui::plot::PlotPoints>();
+                    plot_ui.line(egui::plot::Line::new(points).name(format!("{:?}", item)));
+                }
+                plot_ui.line(egui::plot::Line::new(starved).name("starved buildings"));
             });
     }
 
@@ -388,6 +1175,524 @@ impl ProductivityView {
                 }
             });
     }
+
+    fn show_optimizer(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        let constraints = &mut app.optimizer;
+        let mut budget = constraints.budget.value();
+        ui.add(
+            egui::DragValue::new(&mut budget)
+                .clamp_range(0..=i64::MAX)
+                .speed(10_000)
+                .prefix("budget $"),
+        );
+        constraints.budget = Money::from(budget);
+        egui::ComboBox::from_id_source("optimizer_objective")
+            .selected_text(match constraints.objective {
+                Objective::Profit => "maximize profit",
+                Objective::Roi => "maximize ROI",
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(
+                    &mut constraints.objective,
+                    Objective::Profit,
+                    "maximize profit",
+                );
+                ui.selectable_value(&mut constraints.objective, Objective::Roi, "maximize ROI");
+            });
+        ui.horizontal(|ui| {
+            let mut limited = constraints.max_buildings.is_some();
+            ui.checkbox(&mut limited, "at most");
+            let mut max = constraints.max_buildings.unwrap_or(10);
+            ui.add_enabled(
+                limited,
+                egui::DragValue::new(&mut max)
+                    .clamp_range(1..=optimizer::MAX_STEPS)
+                    .suffix(" buildings"),
+            );
+            constraints.max_buildings = limited.then_some(max);
+        });
+        ui.checkbox(&mut constraints.buy_inputs, "buy missing inputs");
+        ui.checkbox(
+            &mut app.optimizer_plan_types_only,
+            "only building types in the plan",
+        );
+        if ui.button("Optimize").clicked() {
+            let result = app.optimize();
+            app.report_result(result);
+        }
+        let mut chosen = None;
+        for (i, solution) in app.optimizer_solutions.iter().enumerate() {
+            ui.separator();
+            let report = &solution.report;
+            ui.strong(format!(
+                "#{}: {} per month, ROI {}",
+                i + 1,
+                report.monthly_profit(),
+                report
+                    .monthly_roi()
+                    .map_or(String::from("n/a"), |roi| format!("{:.2}%", roi * 100.0))
+            ));
+            ui.label(format!("price: {}", report.total_price()));
+            for cond in solution.conditions.iter() {
+                ui.label(format!(
+                    "  {:?} / {} / {} x{}",
+                    cond.building_type(),
+                    cond.recipe_id().map_or(String::new(), |id| id.to_string()),
+                    cond.worker_wage().map_or(String::new(), |w| w.to_string()),
+                    cond.amount()
+                ));
+            }
+            if ui.button("Use").clicked() {
+                chosen = Some(solution.conditions.clone());
+            }
+        }
+        if let Some(conditions) = chosen {
+            app.plan.conditions = conditions;
+            self.condition_changed = true;
+        }
+    }
+
+    /// 编辑规划的运输设置。条件的地点在条件表的 Location 列中填写。
+    fn show_logistics(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        let logistics = &mut app.plan.logistics;
+        let mut changed = false;
+        egui::ComboBox::from_id_source("logistics_vehicle")
+            .selected_text(format!("default vehicle: {}", logistics.vehicle))
+            .show_ui(ui, |ui| {
+                for vehicle in enum_iterator::all::<Vehicle>() {
+                    changed |= ui
+                        .selectable_value(&mut logistics.vehicle, vehicle, vehicle.to_string())
+                        .changed();
+                }
+            });
+        ui.horizontal(|ui| {
+            ui.label("market at");
+            let mut market = logistics.market.clone().unwrap_or_default();
+            if ui
+                .add(
+                    egui::TextEdit::singleline(&mut market)
+                        .hint_text("none")
+                        .desired_width(100.0),
+                )
+                .changed()
+            {
+                logistics.market = Some(market).filter(|market| !market.is_empty());
+                changed = true;
+            }
+        });
+        let mut remove = None;
+        egui::Grid::new("logistics_routes").show(ui, |ui| {
+            ui.strong("from");
+            ui.strong("to");
+            ui.strong("distance");
+            ui.strong("vehicle");
+            ui.end_row();
+            for (i, route) in logistics.routes.iter_mut().enumerate() {
+                changed |= ui
+                    .add(egui::TextEdit::singleline(&mut route.from).desired_width(80.0))
+                    .changed();
+                changed |= ui
+                    .add(egui::TextEdit::singleline(&mut route.to).desired_width(80.0))
+                    .changed();
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut route.distance)
+                            .clamp_range(0.0..=f64::MAX)
+                            .speed(1.0),
+                    )
+                    .changed();
+                egui::ComboBox::from_id_source(format!("route_vehicle:{}", i))
+                    .selected_text(
+                        route
+                            .vehicle
+                            .map_or(String::from("default"), |v| v.to_string()),
+                    )
+                    .show_ui(ui, |ui| {
+                        changed |= ui
+                            .selectable_value(&mut route.vehicle, None, "default")
+                            .changed();
+                        for vehicle in enum_iterator::all::<Vehicle>() {
+                            changed |= ui
+                                .selectable_value(
+                                    &mut route.vehicle,
+                                    Some(vehicle),
+                                    vehicle.to_string(),
+                                )
+                                .changed();
+                        }
+                    });
+                if ui.button(CROSS).clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            logistics.routes.remove(i);
+            changed = true;
+        }
+        if ui.button("Add route").clicked() {
+            logistics.routes.push(Route {
+                from: String::new(),
+                to: String::new(),
+                distance: 0.0,
+                vehicle: None,
+            });
+            changed = true;
+        }
+        if changed {
+            self.condition_changed = true;
+        }
+    }
+
+    /// 编辑规划的游戏设置。各类别的倍率乘在全局倍率之上。
+    fn show_settings(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        let settings = &mut app.plan.settings;
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("month length");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut settings.month_length)
+                        .clamp_range(1.0..=365.0)
+                        .suffix(" days"),
+                )
+                .changed();
+        });
+        egui::Grid::new("settings_multipliers").show(ui, |ui| {
+            ui.label("");
+            ui.strong("all");
+            for category in enum_iterator::all::<Category>() {
+                ui.strong(format!("{:?}", category));
+            }
+            ui.end_row();
+            for (name, multiplier) in [
+                ("construction", &mut settings.construction),
+                ("upkeep", &mut settings.upkeep),
+                ("speed", &mut settings.speed),
+            ] {
+                ui.label(name);
+                changed |= Self::show_factor(ui, &mut multiplier.global);
+                for category in enum_iterator::all::<Category>() {
+                    let mut factor = multiplier.categories.get(&category).copied().unwrap_or(1.0);
+                    if Self::show_factor(ui, &mut factor) {
+                        if factor == 1.0 {
+                            multiplier.categories.remove(&category);
+                        } else {
+                            multiplier.categories.insert(category, factor);
+                        }
+                        changed = true;
+                    }
+                }
+                ui.end_row();
+            }
+            ui.label("market prices");
+            changed |= Self::show_factor(ui, &mut settings.market.global);
+            ui.end_row();
+        });
+        let mut remove = None;
+        for (item, factor) in settings.market.items.iter_mut() {
+            ui.horizontal(|ui| {
+                ui.label(format!("{:?} price", item));
+                changed |= Self::show_factor(ui, factor);
+                if ui.button(CROSS).clicked() {
+                    remove = Some(*item);
+                }
+            });
+        }
+        if let Some(item) = remove {
+            settings.market.items.remove(&item);
+            changed = true;
+        }
+        egui::ComboBox::from_id_source("settings_market_item")
+            .selected_text("price of one item...")
+            .show_ui(ui, |ui| {
+                for item in enum_iterator::all::<recipe::Item>() {
+                    if !settings.market.items.contains_key(&item)
+                        && ui.selectable_label(false, format!("{:?}", item)).clicked()
+                    {
+                        settings.market.items.insert(item, 1.0);
+                        changed = true;
+                    }
+                }
+            });
+        if ui.button("Reset").clicked() {
+            *settings = Settings::default();
+            changed = true;
+        }
+        if changed {
+            self.condition_changed = true;
+        }
+    }
+
+    fn show_factor(ui: &mut egui::Ui, factor: &mut f64) -> bool {
+        ui.add(
+            egui::DragValue::new(factor)
+                .clamp_range(0.0..=100.0)
+                .speed(0.01)
+                .prefix("×"),
+        )
+        .changed()
+    }
+
+    /// 编辑城镇需求和售价饱和参数。没有城镇时剩余物品全部按市场价卖出。
+    fn show_market(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        let market = &mut app.plan.market;
+        let mut changed = false;
+        let mut remove_town = None;
+        for (i, town) in market.towns.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label("town");
+                changed |= ui
+                    .add(egui::TextEdit::singleline(&mut town.name).desired_width(80.0))
+                    .changed();
+                ui.label("location");
+                let mut location = town.location.clone().unwrap_or_default();
+                if ui
+                    .add(egui::TextEdit::singleline(&mut location).desired_width(80.0))
+                    .changed()
+                {
+                    town.location = (!location.is_empty()).then_some(location);
+                    changed = true;
+                }
+                if ui.button(CROSS).clicked() {
+                    remove_town = Some(i);
+                }
+            });
+            let mut remove_item = None;
+            for (item, demand) in town.demand.iter_mut() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("  {:?}", item));
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(demand)
+                                .clamp_range(0.0..=f64::MAX)
+                                .suffix(" per month"),
+                        )
+                        .changed();
+                    if ui.button(CROSS).clicked() {
+                        remove_item = Some(*item);
+                    }
+                });
+            }
+            if let Some(item) = remove_item {
+                town.demand.remove(&item);
+                changed = true;
+            }
+            egui::ComboBox::from_id_source(format!("market_demand:{}", i))
+                .selected_text("add demand...")
+                .show_ui(ui, |ui| {
+                    for item in enum_iterator::all::<recipe::Item>() {
+                        if !town.demand.contains_key(&item)
+                            && ui.selectable_label(false, format!("{:?}", item)).clicked()
+                        {
+                            town.demand.insert(item, 0.0);
+                            changed = true;
+                        }
+                    }
+                });
+            ui.separator();
+        }
+        if let Some(i) = remove_town {
+            market.towns.remove(i);
+            changed = true;
+        }
+        if ui.button("Add town").clicked() {
+            market.towns.push(Town {
+                name: format!("Town {}", market.towns.len() + 1),
+                location: None,
+                demand: Default::default(),
+            });
+            changed = true;
+        }
+        ui.horizontal(|ui| {
+            ui.label("sells up to");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut market.saturation.max_ratio)
+                        .clamp_range(1.0..=100.0)
+                        .speed(0.01)
+                        .suffix("× demand"),
+                )
+                .changed();
+            ui.label("down to");
+            changed |= ui
+                .add(
+                    egui::DragValue::new(&mut market.saturation.floor)
+                        .clamp_range(0.0..=1.0)
+                        .speed(0.01)
+                        .prefix("×"),
+                )
+                .changed();
+            ui.label("of the price");
+        });
+        let mut remove = None;
+        for (item, town) in market.destinations.iter_mut() {
+            ui.horizontal(|ui| {
+                ui.label(format!("{:?} only sold to", item));
+                changed |= ui
+                    .add(egui::TextEdit::singleline(town).desired_width(80.0))
+                    .changed();
+                if ui.button(CROSS).clicked() {
+                    remove = Some(*item);
+                }
+            });
+        }
+        if let Some(item) = remove {
+            market.destinations.remove(&item);
+            changed = true;
+        }
+        egui::ComboBox::from_id_source("market_destination")
+            .selected_text("sell one item to one town...")
+            .show_ui(ui, |ui| {
+                for item in enum_iterator::all::<recipe::Item>() {
+                    if !market.destinations.contains_key(&item)
+                        && ui.selectable_label(false, format!("{:?}", item)).clicked()
+                    {
+                        let town = market.towns.first().map(|t| t.name.clone());
+                        market.destinations.insert(item, town.unwrap_or_default());
+                        changed = true;
+                    }
+                }
+            });
+        if changed {
+            self.condition_changed = true;
+        }
+    }
+
+    /// 编辑规划已研究的节点。不限制时所有配方和建筑都可用。
+    fn show_research(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        let mut limited = app.plan.researched.is_some();
+        if ui
+            .checkbox(&mut limited, "only use researched recipes and buildings")
+            .changed()
+        {
+            app.plan.researched = limited.then(BTreeSet::new);
+            self.condition_changed = true;
+        }
+        let Some(researched) = &mut app.plan.researched else {
+            return;
+        };
+        for (name, node) in research::tree() {
+            let mut checked = researched.contains(name);
+            let mut text = format!("{} ({} points)", name, node.cost);
+            if !node.requires.is_empty() {
+                text += &format!(", after {}", node.requires.join(", "));
+            }
+            if !ui.checkbox(&mut checked, text).changed() {
+                continue;
+            }
+            if checked {
+                // 同时研究所有前置节点
+                let needed = research::needed([name.as_str()], researched);
+                researched.extend(needed);
+            } else {
+                // 依赖它的节点也一并取消
+                researched.remove(name);
+                while let Some(dependent) = researched
+                    .iter()
+                    .find(|n| {
+                        research::tree().get(*n).is_some_and(|node| {
+                            node.requires.iter().any(|r| !researched.contains(r))
+                        })
+                    })
+                    .cloned()
+                {
+                    researched.remove(&dependent);
+                }
+            }
+            self.condition_changed = true;
+        }
+        let spent = research::cost(&researched.iter().cloned().collect::<Vec<_>>());
+        ui.label(format!("researched: {} points", spent));
+    }
+
+    /// 列出生产该物品的各种方式还需要的研究。原料可以从市场购买，不计入其中。
+    fn show_research_needed(ui: &mut egui::Ui, item: recipe::Item, researched: &BTreeSet<String>) {
+        let requirements = research::requirements(item, researched);
+        let mut locked = requirements
+            .iter()
+            .filter(|r| !r.nodes.is_empty())
+            .peekable();
+        if locked.peek().is_none() {
+            return;
+        }
+        ui.strong("Research needed");
+        for requirement in locked {
+            ui.label(format!(
+                "{:?} / {}: {} ({} points)",
+                requirement.building_type,
+                requirement.recipe_id,
+                requirement.nodes.join(" → "),
+                requirement.cost
+            ));
+        }
+        ui.separator();
+    }
+
+    /// 加入建筑的按钮。未解锁的建筑不能加入，悬停时显示所需的研究。
+    fn show_building_button(ui: &mut egui::Ui, unlocks: Unlocks, building_type: Type) -> bool {
+        let label = match building_type {
+            Type::Collector(t) => format!("{:?}", t),
+            Type::Farm(t) => format!("{:?}", t),
+            Type::Factory(t) => format!("{:?}", t),
+            Type::Depot(t) => format!("{:?}", t),
+        };
+        let missing = unlocks.missing_building(building_type);
+        ui.add_enabled(missing.is_empty(), egui::Button::new(label).small())
+            .on_disabled_hover_text(format!("needs research: {}", missing.join(", ")))
+            .clicked()
+    }
+
+    fn show_sources(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        let Some(item) = app.chain_target else {
+            ui.label("Select an item to build a chain for to compare its sources.");
+            return;
+        };
+        ui.heading(format!("Sources of {:?}", item));
+        ui.horizontal(|ui| {
+            ui.label("wage");
+            Self::show_wage_slider(ui, &mut app.source_wage, "source_wage");
+        });
+        ui.separator();
+        let unlocks = app.unlocks();
+        let sources = source::sources(
+            item,
+            app.source_wage,
+            solver::DEFAULT_OUTBUILDINGS,
+            unlocks,
+            &app.plan.settings,
+        );
+        if sources.is_empty() {
+            ui.label("No unlocked building produces this item; it must be bought.");
+        }
+        if let Some(researched) = &app.plan.researched {
+            Self::show_research_needed(ui, item, researched);
+        }
+        for source in sources.iter() {
+            ui.strong(format!("{:?} / {}", source.building_type, source.recipe_id));
+            ui.label(format!("output: {:.2} per month", source.monthly_output));
+            ui.label(format!("price: {}", source.price));
+            ui.label(format!("upkeep: {} per month", source.monthly_upkeep));
+            ui.label(format!("inputs: {} per month", source.monthly_input_cost));
+            if source.monthly_byproduct_value > Money::zero() {
+                ui.label(format!(
+                    "byproducts: {} per month",
+                    source.monthly_byproduct_value
+                ));
+            }
+            ui.label(format!("unit cost: ${:.2}", source.unit_cost()));
+            ui.label(format!(
+                "capital per unit: ${:.2}",
+                source.capital_per_unit()
+            ));
+            if ui.button("Add").clicked() {
+                app.plan.conditions.push(source.condition(1));
+                self.condition_changed = true;
+            }
+            ui.separator();
+        }
+    }
 }
 
 impl View for ProductivityView {
@@ -397,20 +1702,97 @@ impl View for ProductivityView {
         [
             Some(String::from("Condition table")),
             Some(String::from("Simulation Result")),
-            None,
-            None,
+            Some(String::from("Sources")),
+            Some(String::from("Plan")),
         ]
     }
 
+    fn show_right_panel(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui.collapsing("Optimizer", |ui| {
+                self.show_optimizer(app, ui);
+            });
+            ui.collapsing("Logistics", |ui| {
+                self.show_logistics(app, ui);
+            });
+            ui.collapsing("Research", |ui| {
+                self.show_research(app, ui);
+            });
+            ui.collapsing("Game settings", |ui| {
+                self.show_settings(app, ui);
+            });
+            ui.collapsing("Markets", |ui| {
+                self.show_market(app, ui);
+            });
+            ui.separator();
+            self.show_sources(app, ui);
+        });
+    }
+
+    fn show_top_panel(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("File:");
+            ui.text_edit_singleline(&mut app.path_input);
+            if ui.button("Open").clicked() {
+                let result = app.open_plan(PathBuf::from(&app.path_input));
+                app.report_result(result);
+            }
+            if ui.button("Save").clicked() {
+                let result = app.save_plan();
+                app.report_result(result);
+            }
+            if ui.button("Save As").clicked() {
+                let result = app.save_plan_as(PathBuf::from(&app.path_input));
+                app.report_result(result);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Name:");
+            ui.text_edit_singleline(&mut app.plan.name);
+            ui.label("Notes:");
+            ui.text_edit_singleline(&mut app.plan.notes);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Build chain for");
+            let selected = app
+                .chain_target
+                .map_or(String::from("..."), |item| format!("{:?}", item));
+            egui::ComboBox::from_id_source("chain_target")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for item in enum_iterator::all::<recipe::Item>() {
+                        ui.selectable_value(
+                            &mut app.chain_target,
+                            Some(item),
+                            format!("{:?}", item),
+                        );
+                    }
+                });
+            ui.add(
+                egui::DragValue::new(&mut app.chain_rate)
+                    .clamp_range(0.0..=10_000.0)
+                    .suffix(" per month"),
+            );
+            if ui.button("Build chain").clicked() {
+                let result = app.build_chain();
+                app.report_result(result);
+                self.condition_changed = true;
+            }
+        });
+    }
+
     fn show_bottom_panel(&mut self, app: &mut App, ui: &mut egui::Ui) {
+        let researched = app.plan.researched.clone();
+        let unlocks = Unlocks::new(researched.as_ref());
         ui.horizontal(|ui| {
             const CHUNK_SIZE: usize = 6;
             let types = enum_iterator::all::<types::Collector>().collect::<Vec<_>>();
             for chunk in &types.into_iter().chunks(CHUNK_SIZE) {
                 ui.vertical(|ui| {
                     for building_type in chunk {
-                        if ui.small_button(format!("{:?}", building_type)).clicked() {
-                            app.add_collector(building_type);
+                        if Self::show_building_button(ui, unlocks, Type::Collector(building_type)) {
+                            let result = app.add_collector(building_type);
+                            app.report_result(result);
                             self.condition_changed = true;
                         }
                     }
@@ -420,8 +1802,9 @@ impl View for ProductivityView {
             for chunk in &types.into_iter().chunks(CHUNK_SIZE) {
                 ui.vertical(|ui| {
                     for building_type in chunk {
-                        if ui.small_button(format!("{:?}", building_type)).clicked() {
-                            app.add_farm(building_type);
+                        if Self::show_building_button(ui, unlocks, Type::Farm(building_type)) {
+                            let result = app.add_farm(building_type);
+                            app.report_result(result);
                             self.condition_changed = true;
                         }
                     }
@@ -431,32 +1814,62 @@ impl View for ProductivityView {
             for chunk in &types.into_iter().chunks(CHUNK_SIZE) {
                 ui.vertical(|ui| {
                     for building_type in chunk {
-                        if ui.small_button(format!("{:?}", building_type)).clicked() {
-                            app.add_factory(building_type);
+                        if Self::show_building_button(ui, unlocks, Type::Factory(building_type)) {
+                            let result = app.add_factory(building_type);
+                            app.report_result(result);
                             self.condition_changed = true;
                         }
                     }
                 });
             }
+            ui.vertical(|ui| {
+                for building_type in enum_iterator::all::<types::Depot>() {
+                    if Self::show_building_button(ui, unlocks, Type::Depot(building_type)) {
+                        let result = app.add_depot(building_type);
+                        app.report_result(result);
+                        self.condition_changed = true;
+                    }
+                }
+            });
         });
     }
 
     fn show_left_panel(&mut self, app: &mut App, ui: &mut egui::Ui) {
         egui::ScrollArea::vertical().show(ui, |ui| {
-            let Some(report) = &app.simulation_report else { return };
+            if let Some(e) = &app.error {
+                ui.colored_label(egui::Color32::RED, e);
+                ui.separator();
+            }
+            if ui
+                .checkbox(
+                    &mut app.simulation_options.throttle,
+                    "only use inputs made in this plan",
+                )
+                .changed()
+            {
+                self.condition_changed = true;
+            }
+            let Some(report) = &app.simulation_report else {
+                if let Some(e) = &app.simulation_error {
+                    ui.colored_label(egui::Color32::RED, e);
+                }
+                return;
+            };
             ui.heading("Simulation Report");
             ui.separator();
-            // 生产力汇报
-            let mut prodpair = report
-                .productivity()
-                .iter()
-                .collect::<Vec<(&recipe::Item, &productivity::Speed)>>();
-            prodpair.sort_by(|a, b| b.0.cmp(a.0));
-            prodpair.iter().for_each(|(&k, &v)| {
-                ui.label(format!("{:?}: {:.2} per month", k, v.monthly()));
-            });
-
+            // 物品流量汇报
+            match balance::balance(report) {
+                Ok(balances) => Self::show_balance(ui, &balances),
+                Err(e) => {
+                    ui.colored_label(egui::Color32::RED, e.to_string());
+                }
+            }
             ui.separator();
+            if report.market().enabled() {
+                ui.strong("market:");
+                Self::show_sales(ui, report);
+                ui.separator();
+            }
             ui.strong("total buildings:");
             ui.separator();
             let mut prodpair = report
@@ -467,6 +1880,9 @@ impl View for ProductivityView {
             prodpair.iter().for_each(|(&k, &v)| {
                 ui.label(format!("  {:?}: {}", k, v));
             });
+            ui.strong("contributions:");
+            ui.separator();
+            Self::show_contributions(ui, report);
             ui.heading("Economics");
             ui.separator();
             ui.strong(format!("total price: {}", report.total_price()));
@@ -475,20 +1891,69 @@ impl View for ProductivityView {
                 "monthly material cost: {}",
                 report.monthly_material_cost()
             ));
+            ui.label(format!(
+                "monthly logistics cost: {}",
+                report.monthly_logistics_cost()
+            ));
             ui.label(format!("monthly sales: {}", report.monthly_sales()));
             ui.strong(format!("monthly profit: {}", report.monthly_profit()));
             ui.strong(format!("profit rate: {:.2}%", report.profit_rate() * 100.0));
+            match report.monthly_roi() {
+                Some(roi) => ui.strong(format!(
+                    "ROI: {:.2}% per month, {:.2}% per year",
+                    roi * 100.0,
+                    roi * 1200.0
+                )),
+                None => ui.strong("ROI: n/a"),
+            };
+            match report.payback_months() {
+                Some(months) => ui.strong(format!("break even in {:.1} months", months)),
+                None => ui.strong("never breaks even"),
+            };
+            let points = report
+                .cash_flow(report.cash_flow_horizon())
+                .into_iter()
+                .enumerate()
+                .map(|(month, money)| [month as f64, money.value() as f64])
+                .collect::<egui::plot::PlotPoints>();
+            ui.label("cumulative cash flow:");
+            egui::plot::Plot::new("cash_flow")
+                .height(150.0)
+                .allow_drag(false)
+                .allow_zoom(false)
+                .show(ui, |plot_ui| {
+                    plot_ui.hline(egui::plot::HLine::new(0.0));
+                    plot_ui.line(egui::plot::Line::new(points));
+                });
+            ui.collapsing("Wage advice", |ui| {
+                self.show_wage_advice(app, ui);
+            });
+            ui.collapsing("Make or buy", |ui| {
+                self.show_make_or_buy(app, ui);
+            });
+            ui.collapsing("Price sensitivity", |ui| {
+                Self::show_sensitivity(app, ui);
+            });
+            ui.collapsing("Monte Carlo", |ui| {
+                self.show_monte_carlo(app, ui);
+            });
+            ui.collapsing("Day by day", |ui| {
+                self.show_timeline(app, ui);
+            });
         });
     }
 
     fn show_central_panel(&mut self, app: &mut App, ui: &mut egui::Ui) {
-        const HEADERS: [&'static str; 7] = [
+        const HEADERS: [&str; 10] = [
             "Type",
             "Name",
             "Recipe",
             "Worker Wage",
             "Outbuilding Amount",
             "Amount",
+            "Location",
+            "Monthly Profit",
+            "",
             "",
         ];
         ui.vertical_centered(|ui| {
@@ -498,7 +1963,7 @@ impl View for ProductivityView {
                 .column(Column::auto().at_least(150.0))
                 .columns(Column::auto().at_least(100.0), 2)
                 .striped(true)
-                .columns(Column::auto(), 2)
+                .columns(Column::auto(), 5)
                 .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
                 .header(50.0, |mut header| {
                     for i in HEADERS {
@@ -509,11 +1974,17 @@ impl View for ProductivityView {
                 })
                 .body(|mut body| {
                     self.mark_as_delete = None;
-                    for (i, cond) in app.simulation_conditions.iter_mut().enumerate() {
-                        self.show_body_content(&mut body, i, cond);
+                    self.researched = app.plan.researched.clone();
+                    for (i, cond) in app.plan.conditions.iter_mut().enumerate() {
+                        let error = app.condition_errors.get(i).and_then(|e| e.as_deref());
+                        let contribution = app
+                            .simulation_report
+                            .as_ref()
+                            .and_then(|report| report.contributions().get(i));
+                        self.show_body_content(&mut body, i, cond, contribution, error);
                     }
                     if let Some(idx) = self.mark_as_delete {
-                        app.simulation_conditions.remove(idx);
+                        app.plan.conditions.remove(idx);
                     }
                 });
         });
@@ -522,11 +1993,11 @@ impl View for ProductivityView {
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, _: &mut eframe::Frame) {
+        self.reload_data(ctx);
         let mut view = ProductivityView::new();
         view.show(self, ctx);
         if view.condition_changed {
             self.simulate();
-            view.condition_changed = false;
         }
     }
 }
@@ -0,0 +1,139 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 物品净流量分析：哪些物品有剩余可以出售，哪些不足需要购买，哪些在内部自给自足。
+
+use std::collections::BTreeSet;
+
+use super::{money::Money, productivity::Speed, recipe::Item, Report, Result};
+
+/// 每月净流量绝对值小于该值时视为平衡。
+pub const TOLERANCE: f64 = 1e-6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Flow {
+    /// 产出多于消耗，剩余部分出售（启用市场模型时可能有卖不出去的部分）。
+    Surplus,
+    /// 消耗多于产出，不足部分按市场价购买。
+    Deficit,
+    /// 产出与消耗相等，只在内部流转。
+    Balanced,
+}
+
+/// 某物品每月的流量。
+#[derive(Debug, Clone)]
+pub struct ItemBalance {
+    pub item: Item,
+    pub flow: Flow,
+    pub produced: f64,
+    pub consumed: f64,
+    /// 消耗中由本规划自己生产的部分。
+    pub self_supplied: f64,
+    /// 消耗中需要购买的部分。
+    pub bought: f64,
+    /// 出售的部分。
+    pub sold: f64,
+    /// 剩余中卖不出去的部分。
+    pub unsold: f64,
+    /// 自给部分按市场价计算的价值，即内部流转所节省的原料费。
+    pub internal_value: Money,
+    pub purchase_cost: Money,
+    pub sales: Money,
+}
+
+impl ItemBalance {
+    /// 消耗中自给的比例。没有消耗时为 `None`。
+    pub fn self_supplied_ratio(&self) -> Option<f64> {
+        (self.consumed > 0.0).then(|| self.self_supplied / self.consumed)
+    }
+}
+
+/// 按流向和物品排序的净流量分析。
+pub fn balance(report: &Report) -> Result<Vec<ItemBalance>> {
+    let items = report
+        .produced()
+        .iter()
+        .chain(report.consumed().iter())
+        .map(|(item, _)| *item)
+        .collect::<BTreeSet<_>>();
+    let mut result = vec![];
+    for item in items {
+        let monthly = |s: Speed| s.per_month(report.month_length());
+        let produced = report.produced().get(item).map_or(0.0, monthly);
+        let consumed = report.consumed().get(item).map_or(0.0, monthly);
+        let net = produced - consumed;
+        let flow = if net > TOLERANCE {
+            Flow::Surplus
+        } else if net < -TOLERANCE {
+            Flow::Deficit
+        } else {
+            Flow::Balanced
+        };
+        let self_supplied = produced.min(consumed);
+        let bought = if flow == Flow::Deficit { -net } else { 0.0 };
+        let sale = report.sale(item).filter(|_| flow == Flow::Surplus);
+        let sold = sale.map_or(0.0, |sale| sale.sold);
+        let unsold = sale.map_or(0.0, |sale| sale.unsold());
+        let sales = sale.map_or(0.0, |sale| sale.revenue);
+        let price = report.settings().price(item)?;
+        result.push(ItemBalance {
+            item,
+            flow,
+            produced,
+            consumed,
+            self_supplied,
+            bought,
+            sold,
+            unsold,
+            internal_value: price * self_supplied,
+            purchase_cost: price * bought,
+            sales: Money::from(sales.round() as i64),
+        });
+    }
+    result.sort_by_key(|b| (b.flow, b.item));
+    Ok(result)
+}
+
+#[test]
+fn balance_test() {
+    use super::{wood_chain, Simulator};
+    // 三座木工中心用光伐木场的木材，还要外购
+    let mut conditions = wood_chain();
+    conditions[1].set_amount(3);
+    let report = Simulator::from_conditions(&conditions)
+        .unwrap()
+        .simulate()
+        .unwrap();
+    let balance = balance(&report).unwrap();
+    let wood = balance.iter().find(|b| b.item == Item::Wood).unwrap();
+    let planks = balance
+        .iter()
+        .find(|b| b.item == Item::WoodenPlanks)
+        .unwrap();
+    assert_eq!(planks.flow, Flow::Surplus);
+    assert_eq!(wood.flow, Flow::Deficit);
+    let made = report
+        .produced()
+        .get(Item::Wood)
+        .unwrap()
+        .per_month(report.month_length());
+    assert!((wood.self_supplied - made).abs() < 1e-9);
+    assert!(wood.bought > 0.0);
+    assert!((wood.self_supplied + wood.bought - wood.consumed).abs() < 1e-9);
+    let sales = balance
+        .iter()
+        .map(|b| b.sales)
+        .fold(Money::zero(), |a, b| a + b);
+    assert!((sales - report.monthly_sales()).value().abs() <= 1);
+}
@@ -12,39 +12,81 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display};
 
-use super::{money, productivity::Productivity, recipe::Recipe};
+use super::{data, money, productivity::Productivity, recipe::Recipe};
 
 use enum_iterator_derive::Sequence;
+use serde_derive::{Deserialize, Serialize};
 
 #[derive(Debug)]
 pub enum Error {
-    InfoNotFoundError,
+    InfoNotFound(types::Type),
+    WrongInfoKind(types::Type),
+    NoRecipe(types::Type),
+    WageOutOfRange(WorkerWage),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InfoNotFound(t) => write!(f, "no building info for {:?}", t),
+            Error::WrongInfoKind(t) => write!(f, "building info for {:?} has the wrong kind", t),
+            Error::NoRecipe(t) => write!(f, "{:?} has no recipes", t),
+            Error::WageOutOfRange(wage) => {
+                let wages = &data::current().wages;
+                write!(
+                    f,
+                    "wage {} is outside the allowed range {}%-{}%",
+                    wage, wages.min, wages.max
+                )
+            }
+        }
+    }
 }
 
 pub mod info {
-    use super::super::recipe::Id;
+    use super::super::{data, recipe::Id};
 
     use super::types;
-    use super::{money, types::Type};
+    use super::{money, types::Type, Error};
     use std::collections::HashMap;
 
-    use once_cell::sync::Lazy;
     use serde_derive::Deserialize;
 
-    pub static INFOS: Lazy<HashMap<Type, Info>> = Lazy::new(|| {
-        lazy_static_include_str! {
-            INFO_RAW => "data/building_info.yaml",
-        }
-        serde_yaml::from_str(&INFO_RAW).unwrap()
-    });
+    pub fn infos() -> &'static HashMap<Type, Info> {
+        &data::current().infos
+    }
 
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, PartialEq, Deserialize)]
     pub enum Info {
         Collector(Collector),
         Farm(Farm),
         Factory(Factory),
+        Depot(Depot),
+    }
+
+    impl Info {
+        /// 该建筑可使用的配方。
+        pub fn recipes(&self) -> &[Id] {
+            match self {
+                Info::Collector(x) => &x.recipes,
+                Info::Farm(x) => &x.recipes,
+                Info::Factory(x) => &x.recipes,
+                Info::Depot(_) => &[],
+            }
+        }
+
+        /// 信息的种类是否与建筑类型一致。
+        pub fn matches(&self, building_type: Type) -> bool {
+            matches!(
+                (building_type, self),
+                (Type::Collector(_), Info::Collector(_))
+                    | (Type::Farm(_), Info::Farm(_))
+                    | (Type::Factory(_), Info::Factory(_))
+                    | (Type::Depot(_), Info::Depot(_))
+            )
+        }
     }
 
     impl From<Collector> for Info {
@@ -65,7 +107,13 @@ pub mod info {
         }
     }
 
-    #[derive(Debug, Deserialize)]
+    impl From<Depot> for Info {
+        fn from(value: Depot) -> Self {
+            Info::Depot(value)
+        }
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
     pub struct Collector {
         pub recipes: Vec<Id>,
         pub price: money::Money,
@@ -74,7 +122,7 @@ pub mod info {
         pub collector_upkeep: money::Money,
     }
 
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, PartialEq, Deserialize)]
     pub struct Farm {
         pub recipes: Vec<Id>,
         pub price: money::Money,
@@ -83,57 +131,93 @@ pub mod info {
         pub field_upkeep: money::Money,
     }
 
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, PartialEq, Deserialize)]
     pub struct Factory {
         pub recipes: Vec<Id>,
         pub price: money::Money,
         pub upkeep: money::Money,
     }
 
-    pub fn get(building_type: Type) -> &'static Info {
-        let Some(info) = INFOS.get(&building_type) else {
-            unreachable!()
+    /// 仓库、车站等辅助建筑，没有配方。
+    #[derive(Debug, PartialEq, Deserialize)]
+    pub struct Depot {
+        pub price: money::Money,
+        pub upkeep: money::Money,
+        /// 容量：仓库为可存放的物品数量，车站为可停放的车辆数。
+        pub capacity: u32,
+    }
+
+    pub fn get(building_type: Type) -> Result<&'static Info, Error> {
+        infos()
+            .get(&building_type)
+            .ok_or(Error::InfoNotFound(building_type))
+    }
+
+    pub fn get_collector_info(
+        collector_type: types::Collector,
+    ) -> Result<&'static Collector, Error> {
+        let building_type = Type::Collector(collector_type);
+        let Info::Collector(x) = get(building_type)? else {
+            return Err(Error::WrongInfoKind(building_type));
         };
-        info
+        Ok(x)
     }
 
-    pub fn get_collector_info(collector_type: types::Collector) -> &'static Collector {
-        let Info::Collector(x) = get(Type::Collector(collector_type)) else {unreachable!()};
-        x
+    pub fn get_factory_info(factory_type: types::Factory) -> Result<&'static Factory, Error> {
+        let building_type = Type::Factory(factory_type);
+        let Info::Factory(x) = get(building_type)? else {
+            return Err(Error::WrongInfoKind(building_type));
+        };
+        Ok(x)
     }
 
-    pub fn get_factory_info(factory_type: types::Factory) -> &'static Factory {
-        let Info::Factory(x) = get(Type::Factory(factory_type)) else {unreachable!()};
-        x
+    pub fn get_depot_info(depot_type: types::Depot) -> Result<&'static Depot, Error> {
+        let building_type = Type::Depot(depot_type);
+        let Info::Depot(x) = get(building_type)? else {
+            return Err(Error::WrongInfoKind(building_type));
+        };
+        Ok(x)
     }
 
-    pub fn get_farm_info(farm_type: types::Farm) -> &'static Farm {
-        let Info::Farm(x) = get(Type::Farm(farm_type)) else {unreachable!()};
-        x
+    pub fn get_farm_info(farm_type: types::Farm) -> Result<&'static Farm, Error> {
+        let building_type = Type::Farm(farm_type);
+        let Info::Farm(x) = get(building_type)? else {
+            return Err(Error::WrongInfoKind(building_type));
+        };
+        Ok(x)
     }
 
     #[test]
     fn info_test() {
-        let info = get(Type::Collector(types::Collector::Lumberyard));
+        let info = get(Type::Collector(types::Collector::Lumberyard)).unwrap();
         println!("{:?}", info);
+        assert!(get_farm_info(types::Farm::CropFarm).is_ok());
+        assert!(get_factory_info(types::Factory::ToyFactory).is_ok());
+        assert!(get_depot_info(types::Depot::Warehouse).is_ok());
+        assert!(get(Type::Depot(types::Depot::Warehouse))
+            .unwrap()
+            .recipes()
+            .is_empty());
     }
 }
 
-use info::Info;
-
 pub mod types {
 
     use enum_iterator_derive::Sequence;
-    use serde_derive::Deserialize;
+    use serde_derive::{Deserialize, Serialize};
 
-    #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Deserialize, PartialOrd, Ord)]
+    #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize, PartialOrd, Ord)]
     pub enum Type {
         Collector(Collector),
         Farm(Farm),
         Factory(Factory),
+        Depot(Depot),
     }
 
-    #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Deserialize, Sequence, PartialOrd, Ord)]
+    #[allow(clippy::enum_variant_names)] // 游戏中的建筑名称
+    #[derive(
+        Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize, Sequence, PartialOrd, Ord,
+    )]
     pub enum Collector {
         Lumberyard,       // 伐木场
         IronMine,         // 铁矿
@@ -148,7 +232,10 @@ pub mod types {
         OffShoreOilDrill, // 海上石油钻井平台
     }
 
-    #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Deserialize, Sequence, PartialOrd, Ord)]
+    #[allow(clippy::enum_variant_names)] // 游戏中的建筑名称
+    #[derive(
+        Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize, Sequence, PartialOrd, Ord,
+    )]
     pub enum Farm {
         CropFarm,      // 农场
         LiveStockFarm, // 牧场
@@ -156,7 +243,10 @@ pub mod types {
         Plantation,    // 种植园}
     }
 
-    #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Deserialize, Sequence, PartialOrd, Ord)]
+    #[allow(clippy::enum_variant_names)] // 游戏中的建筑名称
+    #[derive(
+        Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize, Sequence, PartialOrd, Ord,
+    )]
     pub enum Factory {
         DrinksFactory,        // 饮料厂
         PreservationFactory,  // 预制食品厂
@@ -177,6 +267,28 @@ pub mod types {
         AutomobileMegaFactory, // 汽车大型工厂
     }
 
+    #[allow(clippy::enum_variant_names)] // 游戏中的建筑名称
+    #[derive(
+        Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize, Sequence, PartialOrd, Ord,
+    )]
+    pub enum Depot {
+        Warehouse,    // 仓库
+        TruckDepot,   // 卡车站
+        TrainStation, // 火车站
+        ZeppelinHub,  // 飞艇站
+    }
+
+    impl Type {
+        /// 遍历所有建筑类型。
+        pub fn all() -> impl Iterator<Item = Type> {
+            enum_iterator::all::<Collector>()
+                .map(Type::Collector)
+                .chain(enum_iterator::all::<Farm>().map(Type::Farm))
+                .chain(enum_iterator::all::<Factory>().map(Type::Factory))
+                .chain(enum_iterator::all::<Depot>().map(Type::Depot))
+        }
+    }
+
     // impl From<Collector> for Type {
     //     fn from(value: Collector) -> Self {
     //         Type::Collector(value)
@@ -197,7 +309,7 @@ pub mod types {
 use types::Type;
 
 /// 附属建筑数量。
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Sequence)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Sequence, Serialize, Deserialize)]
 pub enum OutbuildingAmount {
     One = 1,
     Two = 2,
@@ -206,8 +318,16 @@ pub enum OutbuildingAmount {
     Five = 5,
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Sequence)]
-pub enum WorkerWage {
+/// 工人工资，以百分比表示。允许的范围和对生产速度、维护费的影响见数据文件 `wages.yaml`。
+///
+/// 旧版规划中的工资是 `Percent25` 到 `Percent200` 的枚举名，读取时仍然接受，对应 [`WorkerWage::PRESETS`]。
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(from = "RawWage")]
+pub struct WorkerWage(u16);
+
+/// 旧版规划的工资枚举。
+#[derive(Deserialize)]
+enum Preset {
     Percent25,
     Percent50,
     Percent75,
@@ -217,43 +337,164 @@ pub enum WorkerWage {
     Percent200,
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawWage {
+    Percent(u16),
+    Preset(Preset),
+}
+
+impl From<RawWage> for WorkerWage {
+    fn from(value: RawWage) -> Self {
+        match value {
+            RawWage::Percent(percent) => WorkerWage(percent),
+            RawWage::Preset(preset) => match preset {
+                Preset::Percent25 => WorkerWage::PERCENT_25,
+                Preset::Percent50 => WorkerWage::PERCENT_50,
+                Preset::Percent75 => WorkerWage::PERCENT_75,
+                Preset::Percent100 => WorkerWage::PERCENT_100,
+                Preset::Percent125 => WorkerWage::PERCENT_125,
+                Preset::Percent150 => WorkerWage::PERCENT_150,
+                Preset::Percent200 => WorkerWage::PERCENT_200,
+            },
+        }
+    }
+}
+
+impl Default for WorkerWage {
+    fn default() -> Self {
+        WorkerWage::PERCENT_100
+    }
+}
+
 impl Display for WorkerWage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                WorkerWage::Percent25 => "25%",
-                WorkerWage::Percent50 => "50%",
-                WorkerWage::Percent75 => "75%",
-                WorkerWage::Percent100 => "100%",
-                WorkerWage::Percent125 => "125%",
-                WorkerWage::Percent150 => "150%",
-                WorkerWage::Percent200 => "200%",
-            }
-        )
+        f.pad(&format!("{}%", self.0))
     }
 }
 
 impl WorkerWage {
-    pub fn value(&self) -> f64 {
-        match self {
-            WorkerWage::Percent25 => 0.25,
-            WorkerWage::Percent50 => 0.50,
-            WorkerWage::Percent75 => 0.75,
-            WorkerWage::Percent100 => 1.00,
-            WorkerWage::Percent125 => 1.25,
-            WorkerWage::Percent150 => 1.50,
-            WorkerWage::Percent200 => 2.00,
+    pub const PERCENT_25: WorkerWage = WorkerWage(25);
+    pub const PERCENT_50: WorkerWage = WorkerWage(50);
+    pub const PERCENT_75: WorkerWage = WorkerWage(75);
+    pub const PERCENT_100: WorkerWage = WorkerWage(100);
+    pub const PERCENT_125: WorkerWage = WorkerWage(125);
+    pub const PERCENT_150: WorkerWage = WorkerWage(150);
+    pub const PERCENT_200: WorkerWage = WorkerWage(200);
+
+    /// 常用的工资档位，即旧版规划中的枚举值。
+    pub const PRESETS: [WorkerWage; 7] = [
+        WorkerWage::PERCENT_25,
+        WorkerWage::PERCENT_50,
+        WorkerWage::PERCENT_75,
+        WorkerWage::PERCENT_100,
+        WorkerWage::PERCENT_125,
+        WorkerWage::PERCENT_150,
+        WorkerWage::PERCENT_200,
+    ];
+
+    pub const fn new(percent: u16) -> WorkerWage {
+        WorkerWage(percent)
+    }
+
+    pub fn percent(&self) -> u16 {
+        self.0
+    }
+
+    /// 生产速度的倍率。
+    pub fn speed(&self) -> f64 {
+        data::current().wages.speed.at(self.0)
+    }
+
+    /// 维护费的倍率。
+    pub fn upkeep(&self) -> f64 {
+        data::current().wages.upkeep.at(self.0)
+    }
+
+    /// 检查工资是否在游戏允许的范围内。
+    fn check(&self) -> Result<(), Error> {
+        let wages = &data::current().wages;
+        if !(wages.min..=wages.max).contains(&self.0) {
+            return Err(Error::WageOutOfRange(*self));
+        }
+        Ok(())
+    }
+}
+
+/// 由（工资百分比，倍率）点组成的折线，点按工资严格递增排列。
+/// 点之间线性插值，超出两端时取端点的倍率。加载时拒绝未按工资严格递增排列的点列。
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "Vec<(u16, f64)>")]
+pub struct Curve(Vec<(u16, f64)>);
+
+impl TryFrom<Vec<(u16, f64)>> for Curve {
+    type Error = String;
+
+    fn try_from(points: Vec<(u16, f64)>) -> Result<Self, Self::Error> {
+        if let Some(w) = points.windows(2).find(|w| w[0].0 >= w[1].0) {
+            return Err(format!(
+                "curve wages must be strictly increasing, but {}% is followed by {}%",
+                w[0].0, w[1].0
+            ));
+        }
+        if let Some((percent, factor)) = points.iter().find(|(_, factor)| !factor.is_finite()) {
+            return Err(format!(
+                "curve factor {} at {}% is not a finite number",
+                factor, percent
+            ));
+        }
+        Ok(Curve(points))
+    }
+}
+
+impl Curve {
+    pub fn points(&self) -> &[(u16, f64)] {
+        &self.0
+    }
+
+    pub fn at(&self, percent: u16) -> f64 {
+        let points = &self.0;
+        let (Some(first), Some(last)) = (points.first(), points.last()) else {
+            return percent as f64 / 100.0;
+        };
+        if percent <= first.0 {
+            return first.1;
+        }
+        if percent >= last.0 {
+            return last.1;
         }
+        let i = points.partition_point(|(x, _)| *x <= percent);
+        let (x0, y0) = points[i - 1];
+        let (x1, y1) = points[i];
+        y0 + (y1 - y0) * (percent - x0) as f64 / (x1 - x0) as f64
     }
 }
 
+/// 工资的允许范围和影响，对应数据文件 `wages.yaml`。
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Wages {
+    pub min: u16,
+    pub max: u16,
+    pub speed: Curve,
+    pub upkeep: Curve,
+}
+
 pub trait Building {
     fn plant_type(&self) -> types::Type;
 
-    /// 计算本建筑的产能。
-    fn productivity(&self) -> Productivity;
+    /// 使用的配方。仓库等辅助建筑没有配方。
+    fn recipe(&self) -> Option<&'static Recipe>;
+
+    /// 配方速度的倍率，由工资和附属建筑数量决定。
+    fn rate(&self) -> f64;
+
+    /// 计算本建筑的产能。没有配方的建筑不生产也不消耗物品。
+    fn productivity(&self) -> super::Result<Productivity> {
+        match self.recipe() {
+            Some(recipe) => Ok(recipe.productivity()? * self.rate()),
+            None => Ok(Productivity::new(HashMap::new())),
+        }
+    }
 
     fn price(&self) -> money::Money;
 
@@ -275,9 +516,8 @@ impl CollectorPlant {
         recipe: &'static Recipe,
         worker_wage: WorkerWage,
     ) -> Result<Self, Error> {
-        let Some(Info::Collector(info)) = info::INFOS.get(&Type::Collector(plant_type)) else {
-                return Err(Error::InfoNotFoundError);
-            };
+        worker_wage.check()?;
+        let info = info::get_collector_info(plant_type)?;
         Ok(CollectorPlant {
             plant_type,
             collector_amount,
@@ -289,9 +529,12 @@ impl CollectorPlant {
 }
 
 impl Building for CollectorPlant {
-    fn productivity(&self) -> Productivity {
-        self.recipe.productivity()
-            * (self.worker_wage.value() * (self.collector_amount as u8) as f64)
+    fn recipe(&self) -> Option<&'static Recipe> {
+        Some(self.recipe)
+    }
+
+    fn rate(&self) -> f64 {
+        self.worker_wage.speed() * (self.collector_amount as u8) as f64
     }
 
     fn price(&self) -> money::Money {
@@ -300,7 +543,7 @@ impl Building for CollectorPlant {
 
     fn upkeep(&self) -> money::Money {
         (self.info.upkeep + self.info.collector_upkeep * self.collector_amount as i64)
-            * self.worker_wage.value()
+            * self.worker_wage.upkeep()
     }
 
     fn plant_type(&self) -> types::Type {
@@ -323,9 +566,8 @@ impl Farm {
         recipe: &'static Recipe,
         worker_wage: WorkerWage,
     ) -> Result<Self, Error> {
-        let Some(Info::Farm(info)) = info::INFOS.get(&Type::Farm(plant_type)) else {
-                return Err(Error::InfoNotFoundError);
-            };
+        worker_wage.check()?;
+        let info = info::get_farm_info(plant_type)?;
         Ok(Farm {
             plant_type,
             field_amount,
@@ -341,8 +583,12 @@ impl Building for Farm {
         Type::Farm(self.plant_type)
     }
 
-    fn productivity(&self) -> Productivity {
-        self.recipe.productivity() * (self.field_amount as u8 as f64 * self.worker_wage.value())
+    fn recipe(&self) -> Option<&'static Recipe> {
+        Some(self.recipe)
+    }
+
+    fn rate(&self) -> f64 {
+        self.field_amount as u8 as f64 * self.worker_wage.speed()
     }
 
     fn price(&self) -> money::Money {
@@ -351,7 +597,7 @@ impl Building for Farm {
 
     fn upkeep(&self) -> money::Money {
         (self.info.upkeep + (self.field_amount as i64 * self.info.field_upkeep))
-            * self.worker_wage.value()
+            * self.worker_wage.upkeep()
     }
 }
 
@@ -368,9 +614,8 @@ impl Factory {
         recipe: &'static Recipe,
         worker_wage: WorkerWage,
     ) -> Result<Self, Error> {
-        let Some(Info::Factory(info)) = info::INFOS.get(&Type::Factory(plant_type)) else {
-                return Err(Error::InfoNotFoundError);
-            };
+        worker_wage.check()?;
+        let info = info::get_factory_info(plant_type)?;
         Ok(Factory {
             plant_type,
             recipe,
@@ -385,8 +630,12 @@ impl Building for Factory {
         Type::Factory(self.plant_type)
     }
 
-    fn productivity(&self) -> Productivity {
-        self.recipe.productivity() * self.worker_wage.value()
+    fn recipe(&self) -> Option<&'static Recipe> {
+        Some(self.recipe)
+    }
+
+    fn rate(&self) -> f64 {
+        self.worker_wage.speed()
     }
 
     fn price(&self) -> money::Money {
@@ -394,6 +643,60 @@ impl Building for Factory {
     }
 
     fn upkeep(&self) -> money::Money {
-        self.info.upkeep * self.worker_wage.value()
+        self.info.upkeep * self.worker_wage.upkeep()
     }
 }
+
+/// 仓库、车站等辅助建筑，只计入价格和维护费。
+pub struct Depot {
+    plant_type: types::Depot,
+    info: &'static info::Depot,
+}
+
+impl Depot {
+    pub fn create(plant_type: types::Depot) -> Result<Self, Error> {
+        let info = info::get_depot_info(plant_type)?;
+        Ok(Depot { plant_type, info })
+    }
+}
+
+impl Building for Depot {
+    fn plant_type(&self) -> Type {
+        Type::Depot(self.plant_type)
+    }
+
+    fn recipe(&self) -> Option<&'static Recipe> {
+        None
+    }
+
+    fn rate(&self) -> f64 {
+        0.0
+    }
+
+    fn price(&self) -> money::Money {
+        self.info.price
+    }
+
+    fn upkeep(&self) -> money::Money {
+        self.info.upkeep
+    }
+}
+
+#[test]
+fn worker_wage_test() {
+    let wages: Vec<WorkerWage> = serde_yaml::from_str("[Percent125, 90]").unwrap();
+    assert_eq!(wages, vec![WorkerWage::PERCENT_125, WorkerWage::new(90)]);
+    assert_eq!(serde_yaml::to_string(&wages[1]).unwrap().trim(), "90");
+    assert_eq!(WorkerWage::new(90).to_string(), "90%");
+
+    let curve = Curve::try_from(vec![(50, 0.5), (100, 1.0), (200, 1.5)]).unwrap();
+    assert_eq!(curve.at(25), 0.5);
+    assert_eq!(curve.at(75), 0.75);
+    assert_eq!(curve.at(150), 1.25);
+    assert_eq!(curve.at(300), 1.5);
+    // 乱序或重复的工资在加载时被拒绝
+    assert!(serde_yaml::from_str::<Curve>("[[100, 1.0], [50, 0.5]]").is_err());
+    assert!(serde_yaml::from_str::<Curve>("[[100, 1.0], [100, 2.0]]").is_err());
+    assert!(serde_yaml::from_str::<Curve>("[[100, .nan]]").is_err());
+    assert!(WorkerWage::new(1000).check().is_err());
+}
@@ -0,0 +1,312 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 游戏数据（建筑、配方、价格、研究树等）的加载。
+//!
+//! 启动时按顺序查找数据目录：命令行参数 `--data-dir`、环境变量 `ROI_CALC_DATA_DIR`、
+//! 配置目录下的 `roi-calc/data`。目录中缺失的文件使用编译时嵌入的副本。
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::{Duration, Instant, SystemTime},
+};
+
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+
+use super::{
+    building::{info::Info, types::Type, Wages},
+    logistics::{Vehicle, VehicleInfo},
+    money::Money,
+    recipe::{Id, Item, Recipe},
+    research::Node,
+    validate::{validate, Problem},
+};
+
+pub const DATA_DIR_ARG: &str = "--data-dir";
+pub const DATA_DIR_ENV: &str = "ROI_CALC_DATA_DIR";
+
+const BUILDING_INFO_FILE: &str = "building_info.yaml";
+const RECIPES_FILE: &str = "recipes.yaml";
+const PRICES_FILE: &str = "global_market_prices.yaml";
+const WAGES_FILE: &str = "wages.yaml";
+const VEHICLES_FILE: &str = "vehicles.yaml";
+const RESEARCH_FILE: &str = "research.yaml";
+
+const FILES: [&str; 6] = [
+    BUILDING_INFO_FILE,
+    RECIPES_FILE,
+    PRICES_FILE,
+    WAGES_FILE,
+    VEHICLES_FILE,
+    RESEARCH_FILE,
+];
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, serde_yaml::Error),
+    /// 数据能解析但不一致。
+    Invalid(Vec<Problem>),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::Invalid(problems) => {
+                write!(f, "inconsistent data: ")?;
+                for (i, problem) in problems.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// 一整套游戏数据。
+#[derive(Debug, PartialEq)]
+pub struct GameData {
+    pub infos: HashMap<Type, Info>,
+    pub recipes: HashMap<Id, Recipe>,
+    pub prices: HashMap<Item, Money>,
+    pub wages: Wages,
+    pub vehicles: HashMap<Vehicle, VehicleInfo>,
+    pub research: BTreeMap<String, Node>,
+}
+
+impl GameData {
+    /// 编译时嵌入的数据。嵌入的数据有误属于程序错误。
+    pub fn embedded() -> GameData {
+        lazy_static_include_str! {
+            INFO_RAW => "data/building_info.yaml",
+            RECIPE_RAW => "data/recipes.yaml",
+            PRICES_RAW => "data/global_market_prices.yaml",
+            WAGES_RAW => "data/wages.yaml",
+            VEHICLES_RAW => "data/vehicles.yaml",
+            RESEARCH_RAW => "data/research.yaml",
+        }
+        match Self::parse(
+            &INFO_RAW,
+            &RECIPE_RAW,
+            &PRICES_RAW,
+            &WAGES_RAW,
+            &VEHICLES_RAW,
+            &RESEARCH_RAW,
+        ) {
+            Ok(x) => x,
+            Err(e) => unreachable!("{}", e),
+        }
+    }
+
+    /// 从目录加载数据，目录中不存在的文件使用嵌入的副本。
+    pub fn load_dir(dir: &Path) -> Result<GameData, Error> {
+        let embedded = Self::embedded();
+        Ok(GameData {
+            infos: load_file(&dir.join(BUILDING_INFO_FILE))?.unwrap_or(embedded.infos),
+            recipes: load_file(&dir.join(RECIPES_FILE))?.unwrap_or(embedded.recipes),
+            prices: load_file(&dir.join(PRICES_FILE))?.unwrap_or(embedded.prices),
+            wages: load_file(&dir.join(WAGES_FILE))?.unwrap_or(embedded.wages),
+            vehicles: load_file(&dir.join(VEHICLES_FILE))?.unwrap_or(embedded.vehicles),
+            research: load_file(&dir.join(RESEARCH_FILE))?.unwrap_or(embedded.research),
+        })
+    }
+
+    fn parse(
+        infos: &str,
+        recipes: &str,
+        prices: &str,
+        wages: &str,
+        vehicles: &str,
+        research: &str,
+    ) -> Result<GameData, Error> {
+        Ok(GameData {
+            infos: parse(BUILDING_INFO_FILE, infos)?,
+            recipes: parse(RECIPES_FILE, recipes)?,
+            prices: parse(PRICES_FILE, prices)?,
+            wages: parse(WAGES_FILE, wages)?,
+            vehicles: parse(VEHICLES_FILE, vehicles)?,
+            research: parse(RESEARCH_FILE, research)?,
+        })
+    }
+}
+
+fn parse<T: DeserializeOwned>(name: &str, raw: &str) -> Result<T, Error> {
+    serde_yaml::from_str(raw).map_err(|e| Error::Parse(PathBuf::from(name), e))
+}
+
+fn load_file<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, Error> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let raw = std::fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+    serde_yaml::from_str(&raw)
+        .map(Some)
+        .map_err(|e| Error::Parse(path.to_owned(), e))
+}
+
+// 安装新数据会泄漏旧数据，以便其他模块继续持有 `&'static` 引用。内容相同时不安装，
+// 因此只有数据文件确实改动时才会泄漏一份；长时间反复修改数据文件时，内存随修改次数增长，重启后释放。
+static CURRENT: Lazy<RwLock<&'static GameData>> =
+    Lazy::new(|| RwLock::new(Box::leak(Box::new(GameData::embedded()))));
+
+/// 当前使用的游戏数据。
+pub fn current() -> &'static GameData {
+    *CURRENT.read().unwrap()
+}
+
+/// 替换当前使用的游戏数据。与当前数据相同时什么也不做，返回 `false`。
+/// 每次替换都会泄漏一份旧数据，见 `CURRENT`。
+pub fn install(data: GameData) -> bool {
+    let mut current = CURRENT.write().unwrap();
+    if **current == data {
+        return false;
+    }
+    *current = Box::leak(Box::new(data));
+    true
+}
+
+/// 按照命令行参数、环境变量、配置目录的顺序查找数据目录。
+pub fn locate(args: impl IntoIterator<Item = String>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == DATA_DIR_ARG {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(dir) = arg
+            .strip_prefix(DATA_DIR_ARG)
+            .and_then(|s| s.strip_prefix('='))
+        {
+            return Some(PathBuf::from(dir));
+        }
+    }
+    if let Some(dir) = std::env::var_os(DATA_DIR_ENV) {
+        return Some(PathBuf::from(dir));
+    }
+    config_dir()
+        .map(|dir| dir.join("roi-calc").join("data"))
+        .filter(|dir| dir.is_dir())
+}
+
+fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = std::env::var_os("APPDATA") {
+        return Some(PathBuf::from(dir));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+}
+
+/// 定期检查数据目录中的文件，发现修改时重新加载。
+pub struct Watcher {
+    dir: PathBuf,
+    modified: [Option<SystemTime>; FILES.len()],
+    last_check: Instant,
+}
+
+impl Watcher {
+    pub const INTERVAL: Duration = Duration::from_secs(1);
+
+    pub fn new(dir: PathBuf) -> Watcher {
+        let modified = modified_times(&dir);
+        Watcher {
+            dir,
+            modified,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// 若文件有修改且内容确实变化则重新加载并安装数据，返回 `Some`；否则返回 `None`。
+    /// 加载失败或新数据不一致时保留原有数据。
+    pub fn poll(&mut self) -> Option<Result<(), Error>> {
+        if self.last_check.elapsed() < Self::INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+        let modified = modified_times(&self.dir);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        let data = match GameData::load_dir(&self.dir) {
+            Ok(data) => data,
+            Err(e) => return Some(Err(e)),
+        };
+        let problems = validate(&data);
+        if !problems.is_empty() {
+            return Some(Err(Error::Invalid(problems)));
+        }
+        install(data).then_some(Ok(()))
+    }
+}
+
+fn modified_times(dir: &Path) -> [Option<SystemTime>; FILES.len()] {
+    FILES.map(|file| {
+        std::fs::metadata(dir.join(file))
+            .and_then(|meta| meta.modified())
+            .ok()
+    })
+}
+
+#[test]
+fn locate_test() {
+    let args = ["roi-calc", "--data-dir", "foo"].map(String::from);
+    assert_eq!(locate(args), Some(PathBuf::from("foo")));
+    let args = ["roi-calc", "--data-dir=bar"].map(String::from);
+    assert_eq!(locate(args), Some(PathBuf::from("bar")));
+}
+
+#[test]
+fn load_dir_fallback_test() {
+    let dir = std::env::temp_dir().join(format!("roi-calc-data-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(PRICES_FILE), "Water: 1\n").unwrap();
+    let data = GameData::load_dir(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(data.prices.len(), 1);
+    assert_eq!(data.recipes.len(), GameData::embedded().recipes.len());
+}
+
+#[test]
+fn install_unchanged_test() {
+    // 内容相同的数据不会替换当前数据
+    let before = current() as *const GameData;
+    assert!(!install(GameData::embedded()));
+    assert!(std::ptr::eq(before, current()));
+}
+
+#[test]
+fn reload_invalid_test() {
+    // 不一致的数据不会被安装
+    let dir = std::env::temp_dir().join(format!("roi-calc-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut watcher = Watcher::new(dir.clone());
+    std::fs::write(dir.join(RECIPES_FILE), "{}\n").unwrap();
+    watcher.last_check = Instant::now() - Watcher::INTERVAL;
+    let before = current() as *const GameData;
+    let result = watcher.poll();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(matches!(result, Some(Err(Error::Invalid(_)))));
+    assert!(std::ptr::eq(before, current()));
+}
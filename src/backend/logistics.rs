@@ -0,0 +1,172 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 运输费用：条件可以放在命名的地点，地点之间的路线给出距离和使用的车辆。
+//!
+//! 每月运输量按车辆容量折算成趟数，每趟费用与距离成正比。没有地点的条件和没有设置市场地点时，
+//! 相关的运输不计费用，与不使用运输模型时相同。
+
+use std::{collections::HashMap, fmt::Display};
+
+use enum_iterator_derive::Sequence;
+use serde_derive::{Deserialize, Serialize};
+
+use super::{data, money::Money, ErrorImpl, Result};
+
+#[derive(
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Clone,
+    Copy,
+    Serialize,
+    Deserialize,
+    Sequence,
+    PartialOrd,
+    Ord,
+)]
+pub enum Vehicle {
+    #[default]
+    Truck, // 卡车
+    Train,    // 火车
+    Zeppelin, // 飞艇
+}
+
+impl Display for Vehicle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            Vehicle::Truck => "truck",
+            Vehicle::Train => "train",
+            Vehicle::Zeppelin => "zeppelin",
+        })
+    }
+}
+
+/// 车辆参数，对应数据文件 `vehicles.yaml`。
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct VehicleInfo {
+    /// 每趟运送的物品数量。
+    pub capacity: f64,
+    /// 每趟每单位距离的费用。
+    pub cost_per_distance: Money,
+}
+
+pub fn vehicles() -> &'static HashMap<Vehicle, VehicleInfo> {
+    &data::current().vehicles
+}
+
+/// 两个地点之间的路线，双向通行。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    pub from: String,
+    pub to: String,
+    pub distance: f64,
+    /// 该路线使用的车辆，为空时使用规划的默认车辆。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vehicle: Option<Vehicle>,
+}
+
+/// 规划的运输设置。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Transport {
+    /// 默认车辆。
+    #[serde(default)]
+    pub vehicle: Vehicle,
+    /// 出售产品和购买原料的市场所在地点。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub market: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<Route>,
+}
+
+impl Transport {
+    /// 两个地点之间的距离和车辆。同一地点距离为零。
+    fn route(&self, from: &str, to: &str) -> Option<(f64, Vehicle)> {
+        if from == to {
+            return Some((0.0, self.vehicle));
+        }
+        self.routes
+            .iter()
+            .find(|r| (r.from == from && r.to == to) || (r.from == to && r.to == from))
+            .map(|r| (r.distance, r.vehicle.unwrap_or(self.vehicle)))
+    }
+
+    /// 每月从 `from` 向 `to` 运送 `monthly` 件物品的费用。任一端没有地点时不计费用。
+    /// 趟数是平均值，不足一车的部分按比例计费，不向上取整。
+    pub fn monthly_cost(&self, from: Option<&str>, to: Option<&str>, monthly: f64) -> Result<f64> {
+        let (Some(from), Some(to)) = (from, to) else {
+            return Ok(0.0);
+        };
+        let Some((distance, vehicle)) = self.route(from, to) else {
+            return Err(ErrorImpl::NoRoute(from.to_owned(), to.to_owned()).into());
+        };
+        if distance == 0.0 || monthly <= 0.0 {
+            return Ok(0.0);
+        }
+        let info = vehicles()
+            .get(&vehicle)
+            .ok_or(ErrorImpl::MissingVehicle(vehicle))?;
+        let trips = monthly / info.capacity;
+        Ok(trips * distance * info.cost_per_distance.value() as f64)
+    }
+}
+
+#[test]
+fn logistics_test() {
+    let logistics = Transport {
+        vehicle: Vehicle::Truck,
+        market: Some(String::from("City")),
+        routes: vec![
+            Route {
+                from: String::from("Forest"),
+                to: String::from("City"),
+                distance: 10.0,
+                vehicle: None,
+            },
+            Route {
+                from: String::from("Forest"),
+                to: String::from("Mill"),
+                distance: 20.0,
+                vehicle: Some(Vehicle::Train),
+            },
+        ],
+    };
+    let truck = &vehicles()[&Vehicle::Truck];
+    let train = &vehicles()[&Vehicle::Train];
+    let cost = logistics
+        .monthly_cost(Some("City"), Some("Forest"), 100.0)
+        .unwrap();
+    let expected = 100.0 / truck.capacity * 10.0 * truck.cost_per_distance.value() as f64;
+    assert!((cost - expected).abs() < 1e-9);
+    let cost = logistics
+        .monthly_cost(Some("Forest"), Some("Mill"), 100.0)
+        .unwrap();
+    let expected = 100.0 / train.capacity * 20.0 * train.cost_per_distance.value() as f64;
+    assert!((cost - expected).abs() < 1e-9);
+    assert_eq!(
+        logistics
+            .monthly_cost(Some("Mill"), Some("Mill"), 100.0)
+            .unwrap(),
+        0.0
+    );
+    assert_eq!(
+        logistics.monthly_cost(None, Some("Mill"), 100.0).unwrap(),
+        0.0
+    );
+    assert!(logistics
+        .monthly_cost(Some("Mill"), Some("City"), 100.0)
+        .is_err());
+}
@@ -0,0 +1,401 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 自制还是外购：对规划消耗的每种中间物品，比较按市场价购买和自建生产链的单位成本。
+//!
+//! 自制成本包括按回收期摊销的建筑投资、维护费和上游原料，配方的副产品按市场价抵扣。
+//! 上游原料同样取自制和外购中较便宜的一种，因此自制成本是整条生产链的成本。
+//! 价格、速度和月长都按规划的游戏设置计算。
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    building::{types::Type, OutbuildingAmount, WorkerWage},
+    plan::Plan,
+    recipe::{Id, Item},
+    research::Unlocks,
+    settings::{Category, Settings},
+    source, Condition, Result, Simulator,
+};
+
+/// 默认的投资摊销月数。
+pub const DEFAULT_HORIZON: f64 = 24.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// 建筑投资在多少个月内摊销。
+    pub horizon: f64,
+    /// 规划外的生产方式使用的工资和附属建筑数量。
+    pub worker_wage: WorkerWage,
+    pub outbuilding_amount: OutbuildingAmount,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            horizon: DEFAULT_HORIZON,
+            worker_wage: WorkerWage::PERCENT_100,
+            outbuilding_amount: OutbuildingAmount::Five,
+        }
+    }
+}
+
+/// 用单座建筑自制某物品时每单位的成本构成。
+#[derive(Debug, Clone)]
+pub struct MakeCost {
+    pub building_type: Type,
+    pub recipe_id: Id,
+    pub worker_wage: WorkerWage,
+    pub outbuilding_amount: OutbuildingAmount,
+    /// 单座建筑每月的产量。
+    pub monthly_output: f64,
+    /// 摊销的建筑投资。
+    pub capital: f64,
+    pub upkeep: f64,
+    /// 上游原料，各自取自制和外购中较便宜的一种。
+    pub inputs: f64,
+    /// 副产品按市场价抵扣的部分。
+    pub byproducts: f64,
+}
+
+impl MakeCost {
+    pub fn unit_cost(&self) -> f64 {
+        self.capital + self.upkeep + self.inputs - self.byproducts
+    }
+
+    /// 满足每月 `monthly` 件所需的建筑数量。
+    pub fn amount_for(&self, monthly: f64) -> u8 {
+        (monthly / self.monthly_output)
+            .ceil()
+            .clamp(1.0, u8::MAX as f64) as u8
+    }
+
+    pub fn condition(&self, amount: u8) -> Condition {
+        Condition::new(
+            self.building_type,
+            self.recipe_id.clone(),
+            self.worker_wage,
+            self.outbuilding_amount,
+            amount,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Make,
+    Buy,
+    /// 既买不到也造不出。
+    Unavailable,
+}
+
+/// 规划消耗的一种物品。
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub item: Item,
+    /// 规划每月消耗的数量。
+    pub consumed: f64,
+    /// 规划每月生产的数量。
+    pub produced: f64,
+    /// 单位市场价，没有价格时为 `None`。
+    pub buy: Option<f64>,
+    /// 最便宜的自制方式，没有能生产它的已解锁建筑时为 `None`。
+    pub make: Option<MakeCost>,
+}
+
+impl Row {
+    pub fn verdict(&self) -> Verdict {
+        match (self.buy, &self.make) {
+            (Some(buy), Some(make)) if make.unit_cost() < buy => Verdict::Make,
+            (Some(_), _) => Verdict::Buy,
+            (None, Some(_)) => Verdict::Make,
+            (None, None) => Verdict::Unavailable,
+        }
+    }
+
+    /// 按建议的做法满足规划的全部消耗，每月比另一种做法节省的费用。只有一种做法时为 `None`。
+    pub fn monthly_saving(&self) -> Option<f64> {
+        let make = self.make.as_ref()?.unit_cost();
+        Some((self.buy? - make).abs() * self.consumed)
+    }
+}
+
+struct Analyzer<'a> {
+    settings: &'a Settings,
+    unlocks: Unlocks<'a>,
+    config: Config,
+    /// 规划中生产各物品的条件，作为自制方式的候选。
+    planned: HashMap<Item, Vec<Condition>>,
+    costs: HashMap<Item, Option<f64>>,
+    /// 正在计算的物品，外层在前。
+    visiting: Vec<Item>,
+    /// 因循环依赖按外购计的物品在 `visiting` 中的最小位置，没有时为 `usize::MAX`。
+    cut: usize,
+}
+
+impl<'a> Analyzer<'a> {
+    fn buy(&self, item: Item) -> Option<f64> {
+        self.settings
+            .price(item)
+            .ok()
+            .map(|price| price.value() as f64)
+    }
+
+    /// 自制与外购中较低的单位成本。正在计算的物品（循环依赖）只按外购计。
+    /// 结果依赖外层被这样截断的物品时不缓存，否则结果会随计算顺序变化。
+    fn unit_cost(&mut self, item: Item) -> Result<Option<f64>> {
+        if let Some(cost) = self.costs.get(&item) {
+            return Ok(*cost);
+        }
+        let buy = self.buy(item);
+        if let Some(depth) = self.visiting.iter().position(|x| *x == item) {
+            self.cut = self.cut.min(depth);
+            return Ok(buy);
+        }
+        let depth = self.visiting.len();
+        self.visiting.push(item);
+        let outer = std::mem::replace(&mut self.cut, usize::MAX);
+        let make = self.make(item)?.map(|make| make.unit_cost());
+        self.visiting.pop();
+        let cut = std::mem::replace(&mut self.cut, outer);
+        self.cut = self.cut.min(cut);
+        let cost = match (buy, make) {
+            (Some(buy), Some(make)) => Some(buy.min(make)),
+            (buy, make) => buy.or(make),
+        };
+        if cut >= depth {
+            self.costs.insert(item, cost);
+        }
+        Ok(cost)
+    }
+
+    /// 单座建筑按 `cond` 生产 `item` 的单位成本。不产出该物品或有原料无法取得时为 `None`。
+    fn cost(&mut self, item: Item, cond: &Condition) -> Result<Option<MakeCost>> {
+        let Some(recipe_id) = cond.recipe_id().cloned() else {
+            return Ok(None);
+        };
+        let building = cond.create_building()?;
+        let category = Category::from(building.plant_type());
+        let month_length = self.settings.month_length;
+        let productivity = building.productivity()? * self.settings.speed.get(category);
+        let monthly_output = productivity
+            .get(item)
+            .map_or(0.0, |speed| speed.per_month(month_length));
+        if monthly_output <= 0.0 {
+            return Ok(None);
+        }
+        let price = (building.price() * self.settings.construction.get(category)).value() as f64;
+        let upkeep = (building.upkeep() * self.settings.upkeep.get(category)).value() as f64;
+        let mut inputs = 0.0;
+        let mut byproducts = 0.0;
+        for (other, speed) in productivity.iter() {
+            let monthly = speed.per_month(month_length);
+            if *other == item {
+                continue;
+            } else if monthly < 0.0 {
+                let Some(cost) = self.unit_cost(*other)? else {
+                    return Ok(None);
+                };
+                inputs += cost * -monthly;
+            } else {
+                byproducts += self.buy(*other).unwrap_or(0.0) * monthly;
+            }
+        }
+        let (worker_wage, outbuilding_amount) = match cond {
+            Condition::Collector {
+                worker_wage,
+                collector_amount,
+                ..
+            } => (*worker_wage, *collector_amount),
+            Condition::Farm {
+                worker_wage,
+                field_amount,
+                ..
+            } => (*worker_wage, *field_amount),
+            _ => (
+                cond.worker_wage().unwrap_or(self.config.worker_wage),
+                self.config.outbuilding_amount,
+            ),
+        };
+        Ok(Some(MakeCost {
+            building_type: cond.building_type(),
+            recipe_id,
+            worker_wage,
+            outbuilding_amount,
+            monthly_output,
+            capital: price / self.config.horizon / monthly_output,
+            upkeep: upkeep / monthly_output,
+            inputs: inputs / monthly_output,
+            byproducts: byproducts / monthly_output,
+        }))
+    }
+
+    /// 最便宜的自制方式。候选为规划中已有的生产方式和所有已解锁的建筑配方。
+    fn make(&mut self, item: Item) -> Result<Option<MakeCost>> {
+        let mut candidates = self.planned.get(&item).cloned().unwrap_or_default();
+        candidates.extend(source::producers(item, self.unlocks).into_iter().map(
+            |(building_type, recipe_id)| {
+                Condition::new(
+                    building_type,
+                    recipe_id,
+                    self.config.worker_wage,
+                    self.config.outbuilding_amount,
+                    1,
+                )
+            },
+        ));
+        let mut best: Option<MakeCost> = None;
+        for cond in candidates.iter() {
+            if let Some(cost) = self.cost(item, cond)? {
+                if best
+                    .as_ref()
+                    .is_none_or(|best| cost.unit_cost() < best.unit_cost())
+                {
+                    best = Some(cost);
+                }
+            }
+        }
+        Ok(best)
+    }
+}
+
+/// 对规划消耗的每种物品给出自制和外购的单位成本，按物品排序。
+pub fn analyze(plan: &Plan, config: Config) -> Result<Vec<Row>> {
+    let report = Simulator::for_plan(plan)?.simulate()?;
+    let settings = report.settings();
+    let mut planned: HashMap<Item, Vec<Condition>> = HashMap::new();
+    for cond in plan.conditions.iter() {
+        let mut single = cond.clone();
+        single.set_amount(1);
+        for (item, speed) in cond.productivity()?.iter() {
+            if speed.per_month(settings.month_length) > 0.0 {
+                planned.entry(*item).or_default().push(single.clone());
+            }
+        }
+    }
+    let mut analyzer = Analyzer {
+        settings,
+        unlocks: Unlocks::new(plan.researched.as_ref()),
+        config,
+        planned,
+        costs: HashMap::new(),
+        visiting: vec![],
+        cut: usize::MAX,
+    };
+    let consumed = report
+        .consumed()
+        .iter()
+        .map(|(item, speed)| (*item, speed.per_month(report.month_length())))
+        .collect::<BTreeMap<_, _>>();
+    let mut result = vec![];
+    for (item, consumed) in consumed {
+        if consumed <= 0.0 {
+            continue;
+        }
+        result.push(Row {
+            item,
+            consumed,
+            produced: report
+                .produced()
+                .get(item)
+                .map_or(0.0, |speed| speed.per_month(report.month_length())),
+            buy: analyzer.buy(item),
+            make: analyzer.make(item)?,
+        });
+    }
+    Ok(result)
+}
+
+#[test]
+fn make_or_buy_test() {
+    use super::building::types;
+    let plan = Plan {
+        conditions: vec![Condition::new(
+            Type::Factory(types::Factory::CarpentryCenter),
+            Id(String::from("WoodenPlanks")),
+            WorkerWage::PERCENT_100,
+            OutbuildingAmount::Five,
+            2,
+        )],
+        ..Default::default()
+    };
+    let rows = analyze(&plan, Config::default()).unwrap();
+    assert_eq!(rows.len(), 1);
+    let wood = &rows[0];
+    assert_eq!(wood.item, Item::Wood);
+    assert!(wood.consumed > 0.0);
+    assert_eq!(wood.produced, 0.0);
+    assert_eq!(wood.buy, Some(Item::Wood.price().unwrap().value() as f64));
+    let make = wood.make.as_ref().unwrap();
+    assert!(make.capital > 0.0 && make.upkeep > 0.0);
+    let amount = make.amount_for(wood.consumed);
+    assert!(make.monthly_output * amount as f64 >= wood.consumed);
+    assert_eq!(make.condition(amount).amount(), amount);
+    assert!(wood.monthly_saving().unwrap() >= 0.0);
+
+    // 摊销期越长，自制越便宜
+    let long = analyze(
+        &plan,
+        Config {
+            horizon: DEFAULT_HORIZON * 10.0,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let long = long[0].make.as_ref().unwrap();
+    assert!(long.unit_cost() < make.unit_cost());
+
+    // 建筑加价时自制变贵
+    let expensive = Plan {
+        settings: Settings {
+            construction: super::settings::Multiplier {
+                global: 100.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        ..plan.clone()
+    };
+    let rows = analyze(&expensive, Config::default()).unwrap();
+    assert_eq!(rows[0].verdict(), Verdict::Buy);
+}
+
+#[test]
+fn unit_cost_order_test() {
+    let settings = Settings::default();
+    let analyzer = || Analyzer {
+        settings: &settings,
+        unlocks: Unlocks::default(),
+        config: Config::default(),
+        planned: HashMap::new(),
+        costs: HashMap::new(),
+        visiting: vec![],
+        cut: usize::MAX,
+    };
+    let items = enum_iterator::all::<Item>().collect::<Vec<_>>();
+    // 无论先算哪些物品，每种物品的成本都相同
+    let mut forward = analyzer();
+    let forward = items
+        .iter()
+        .map(|item| forward.unit_cost(*item).unwrap())
+        .collect::<Vec<_>>();
+    let mut backward = analyzer();
+    let mut backward = items
+        .iter()
+        .rev()
+        .map(|item| backward.unit_cost(*item).unwrap())
+        .collect::<Vec<_>>();
+    backward.reverse();
+    assert_eq!(forward, backward);
+}
@@ -0,0 +1,273 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 市场模型：各城镇对物品有每月需求。供给不超过需求时按市场价出售，超过后售价逐渐下降，
+//! 供给达到需求的 `max_ratio` 倍时降到市场价的 `floor` 倍，再多的部分卖不出去。
+//!
+//! 没有城镇时不启用市场模型，所有剩余物品都按市场价卖出。
+
+use std::collections::{BTreeMap, HashSet};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{recipe::Item, ErrorImpl, Result};
+
+/// 一个城镇或地区。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Town {
+    pub name: String,
+    /// 运输设置中的地点名称，用于计算运到该城镇的费用。为 `None` 时运往市场所在地。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// 每月需求。
+    #[serde(default)]
+    pub demand: BTreeMap<Item, f64>,
+}
+
+/// 供给超过需求后售价的下降方式。
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Saturation {
+    /// 最多能卖出需求的多少倍。
+    pub max_ratio: f64,
+    /// 供给达到 `max_ratio` 倍需求时，最后一件的售价与市场价之比。
+    pub floor: f64,
+}
+
+impl Default for Saturation {
+    fn default() -> Self {
+        Saturation {
+            max_ratio: 2.0,
+            floor: 0.5,
+        }
+    }
+}
+
+/// 规划的市场设置。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Market {
+    #[serde(default)]
+    pub towns: Vec<Town>,
+    #[serde(default)]
+    pub saturation: Saturation,
+    /// 指定某物品只卖给某城镇。未指定的物品按需求比例分给所有需要它的城镇，这样的分配收入最高。
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub destinations: BTreeMap<Item, String>,
+}
+
+/// 卖给某城镇的部分。
+#[derive(Debug, Clone, PartialEq)]
+pub struct TownSale {
+    pub town: String,
+    pub sold: f64,
+    pub revenue: f64,
+}
+
+/// 某物品每月剩余部分的销售情况。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sale {
+    pub item: Item,
+    /// 每月剩余的数量。
+    pub offered: f64,
+    pub sold: f64,
+    pub revenue: f64,
+    /// 各城镇的销售情况。不启用市场模型时为空。
+    pub towns: Vec<TownSale>,
+}
+
+impl Sale {
+    /// 每月卖不出去的数量。
+    pub fn unsold(&self) -> f64 {
+        (self.offered - self.sold).max(0.0)
+    }
+}
+
+impl Market {
+    /// 是否启用市场模型。
+    pub fn enabled(&self) -> bool {
+        !self.towns.is_empty()
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Market::default()
+    }
+
+    pub fn town(&self, name: &str) -> Option<&Town> {
+        self.towns.iter().find(|town| town.name == name)
+    }
+
+    /// 城镇名称不重复，需求非负，饱和参数有效，指定的城镇存在。
+    pub fn check(&self) -> Result<()> {
+        let invalid = |reason: String| Err(ErrorImpl::InvalidMarket(reason).into());
+        let saturation = self.saturation;
+        if !(saturation.max_ratio >= 1.0 && saturation.max_ratio.is_finite()) {
+            return invalid(format!(
+                "max ratio {} must be at least 1",
+                saturation.max_ratio
+            ));
+        }
+        if !(0.0..=1.0).contains(&saturation.floor) {
+            return invalid(format!(
+                "price floor {} must be between 0 and 1",
+                saturation.floor
+            ));
+        }
+        let mut names = HashSet::new();
+        for town in self.towns.iter() {
+            if !names.insert(town.name.as_str()) {
+                return invalid(format!("town \"{}\" is listed twice", town.name));
+            }
+            if let Some((item, demand)) = town
+                .demand
+                .iter()
+                .find(|(_, demand)| !(**demand >= 0.0 && demand.is_finite()))
+            {
+                return invalid(format!(
+                    "demand {} for {:?} in \"{}\" must not be negative",
+                    demand, item, town.name
+                ));
+            }
+        }
+        for (item, name) in self.destinations.iter() {
+            if self.town(name).is_none() {
+                return invalid(format!("{:?} is sent to unknown town \"{}\"", item, name));
+            }
+        }
+        Ok(())
+    }
+
+    /// 以市场价 `price` 出售每月 `offered` 件 `item`。
+    ///
+    /// 各城镇先按需求以原价卖出；剩余的部分按需求比例继续分配，此时各城镇的边际售价相同，总收入最高。
+    pub fn sell(&self, item: Item, offered: f64, price: f64) -> Sale {
+        if !self.enabled() {
+            return Sale {
+                item,
+                offered,
+                sold: offered,
+                revenue: price * offered,
+                towns: vec![],
+            };
+        }
+        let targets = match self.destinations.get(&item) {
+            Some(name) => self.towns.iter().filter(|t| t.name == *name).collect(),
+            None => self.towns.iter().collect::<Vec<_>>(),
+        };
+        let demands = targets
+            .iter()
+            .map(|town| town.demand.get(&item).copied().unwrap_or(0.0))
+            .collect::<Vec<_>>();
+        let total_demand = demands.iter().sum::<f64>();
+        let extra_ratio = self.saturation.max_ratio - 1.0;
+        let mut sale = Sale {
+            item,
+            offered,
+            sold: 0.0,
+            revenue: 0.0,
+            towns: vec![],
+        };
+        if total_demand <= 0.0 {
+            return sale;
+        }
+        let base = offered.min(total_demand);
+        let extra = (offered - base).clamp(0.0, extra_ratio * total_demand);
+        // 超出需求部分的分配比例：0 表示没有超出，1 表示各城镇都达到上限
+        let fill = if extra_ratio > 0.0 {
+            extra / (extra_ratio * total_demand)
+        } else {
+            0.0
+        };
+        for (town, demand) in targets.iter().zip(demands) {
+            if demand <= 0.0 {
+                continue;
+            }
+            let full = base * demand / total_demand;
+            let over = fill * extra_ratio * demand;
+            // 售价从 `price` 线性降到 `price * floor`
+            let slope = if over > 0.0 {
+                price * (1.0 - self.saturation.floor) / (extra_ratio * demand)
+            } else {
+                0.0
+            };
+            let revenue = price * (full + over) - slope * over * over / 2.0;
+            sale.sold += full + over;
+            sale.revenue += revenue;
+            sale.towns.push(TownSale {
+                town: town.name.clone(),
+                sold: full + over,
+                revenue,
+            });
+        }
+        sale
+    }
+}
+
+#[test]
+fn market_test() {
+    let market = Market::default();
+    assert!(!market.enabled());
+    let sale = market.sell(Item::Wood, 10.0, 100.0);
+    assert_eq!(sale.revenue, 1000.0);
+    assert_eq!(sale.unsold(), 0.0);
+
+    let market = Market {
+        towns: vec![
+            Town {
+                name: String::from("A"),
+                location: None,
+                demand: BTreeMap::from([(Item::Wood, 10.0)]),
+            },
+            Town {
+                name: String::from("B"),
+                location: None,
+                demand: BTreeMap::from([(Item::Wood, 30.0)]),
+            },
+        ],
+        ..Default::default()
+    };
+    market.check().unwrap();
+
+    // 不超过需求时按原价卖出，按需求比例分配
+    let sale = market.sell(Item::Wood, 20.0, 100.0);
+    assert!((sale.revenue - 2000.0).abs() < 1e-9);
+    assert!((sale.towns[0].sold - 5.0).abs() < 1e-9);
+    assert!((sale.towns[1].sold - 15.0).abs() < 1e-9);
+
+    // 达到上限时超出部分的平均售价为 (1 + floor) / 2
+    let sale = market.sell(Item::Wood, 100.0, 100.0);
+    assert!((sale.sold - 80.0).abs() < 1e-9);
+    assert!((sale.unsold() - 20.0).abs() < 1e-9);
+    assert!((sale.revenue - (4000.0 + 40.0 * 75.0)).abs() < 1e-6);
+
+    // 收入随供给增加但边际收入递减
+    let r1 = market.sell(Item::Wood, 50.0, 100.0).revenue;
+    let r2 = market.sell(Item::Wood, 60.0, 100.0).revenue;
+    let r3 = market.sell(Item::Wood, 70.0, 100.0).revenue;
+    assert!(r1 < r2 && r2 < r3);
+    assert!(r3 - r2 < r2 - r1);
+
+    // 没有城镇需要的物品卖不出去
+    let sale = market.sell(Item::Water, 10.0, 100.0);
+    assert_eq!(sale.sold, 0.0);
+    assert_eq!(sale.unsold(), 10.0);
+
+    // 指定城镇时只卖给该城镇
+    let mut only_a = market.clone();
+    only_a.destinations.insert(Item::Wood, String::from("A"));
+    let sale = only_a.sell(Item::Wood, 30.0, 100.0);
+    assert!((sale.sold - 20.0).abs() < 1e-9);
+    assert_eq!(sale.towns.len(), 1);
+
+    only_a.destinations.insert(Item::Wood, String::from("C"));
+    assert!(only_a.check().is_err());
+}
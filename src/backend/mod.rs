@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod data;
+
 pub mod money;
 
 pub mod building;
@@ -20,7 +22,39 @@ pub mod recipe;
 
 pub mod productivity;
 
-use std::collections::HashMap;
+pub mod plan;
+
+pub mod balance;
+
+pub mod logistics;
+
+pub mod market;
+
+pub mod sensitivity;
+
+pub mod monte_carlo;
+
+pub mod optimizer;
+
+pub mod wage_advisor;
+
+pub mod make_or_buy;
+
+pub mod solver;
+
+pub mod source;
+
+pub mod research;
+
+pub mod settings;
+
+pub mod validate;
+
+pub mod timeline;
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde_derive::{Deserialize, Serialize};
 
 use building::types;
 
@@ -32,6 +66,24 @@ pub struct Error(Box<ErrorImpl>);
 #[derive(Debug)]
 pub enum ErrorImpl {
     SimulatorCreationFailed(building::Error),
+    UnknownRecipe(Id),
+    MissingPrice(recipe::Item),
+    NoRoute(String, String),
+    MissingVehicle(logistics::Vehicle),
+    InvalidSettings(String),
+    InvalidMarket(String),
+    InvalidNoise(String),
+    PlanFailed(plan::Error),
+    /// 配方的 `day_to_gen` 为零，速率无穷大。
+    ZeroDayToGen,
+    InvalidRate(String),
+    OutputFailed(serde_json::Error),
+    /// 逐日模拟的天数超过 `timeline::MAX_DAYS`。
+    TooManyDays(u32),
+    /// 价格敏感性的变化范围不是有限的正数。
+    InvalidRange(f64),
+    /// 蒙特卡洛模拟的次数为零。
+    NoRuns,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -42,15 +94,54 @@ impl From<building::Error> for Error {
     }
 }
 
+impl From<plan::Error> for Error {
+    fn from(value: plan::Error) -> Self {
+        Self(Box::new(ErrorImpl::PlanFailed(value)))
+    }
+}
+
+impl From<ErrorImpl> for Error {
+    fn from(value: ErrorImpl) -> Self {
+        Self(Box::new(value))
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Error: {:?}", self.0)
+        match self.0.as_ref() {
+            ErrorImpl::SimulatorCreationFailed(e) => write!(f, "{}", e),
+            ErrorImpl::UnknownRecipe(id) => write!(f, "unknown recipe \"{}\"", id),
+            ErrorImpl::MissingPrice(item) => write!(f, "no price for {:?}", item),
+            ErrorImpl::NoRoute(from, to) => {
+                write!(f, "no route between \"{}\" and \"{}\"", from, to)
+            }
+            ErrorImpl::MissingVehicle(vehicle) => write!(f, "no data for {}", vehicle),
+            ErrorImpl::InvalidSettings(reason) => write!(f, "invalid game settings: {}", reason),
+            ErrorImpl::InvalidMarket(reason) => write!(f, "invalid market: {}", reason),
+            ErrorImpl::InvalidNoise(reason) => write!(f, "invalid noise: {}", reason),
+            ErrorImpl::PlanFailed(e) => write!(f, "{}", e),
+            ErrorImpl::ZeroDayToGen => write!(f, "recipe has day_to_gen 0"),
+            ErrorImpl::InvalidRate(reason) => write!(f, "invalid rate: {}", reason),
+            ErrorImpl::OutputFailed(e) => write!(f, "failed to write output: {}", e),
+            ErrorImpl::TooManyDays(days) => write!(
+                f,
+                "cannot simulate {} days, at most {}",
+                days,
+                timeline::MAX_DAYS
+            ),
+            ErrorImpl::InvalidRange(range) => write!(
+                f,
+                "invalid price range {}%: must be positive and finite",
+                range * 100.0
+            ),
+            ErrorImpl::NoRuns => write!(f, "Monte Carlo needs at least one run"),
+        }
     }
 }
 
 impl std::error::Error for Error {}
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Condition {
     Collector {
         building_type: types::Collector,
@@ -58,6 +149,9 @@ pub enum Condition {
         worker_wage: building::WorkerWage,
         collector_amount: building::OutbuildingAmount,
         amount: u8,
+        /// 所在地点，为空时不计运输费用。
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        location: Option<String>,
     },
     Farm {
         building_type: types::Farm,
@@ -65,24 +159,274 @@ pub enum Condition {
         worker_wage: building::WorkerWage,
         field_amount: building::OutbuildingAmount,
         amount: u8,
+        /// 所在地点，为空时不计运输费用。
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        location: Option<String>,
     },
     Factory {
         building_type: types::Factory,
         recipe_id: Id,
         worker_wage: building::WorkerWage,
         amount: u8,
+        /// 所在地点，为空时不计运输费用。
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        location: Option<String>,
     },
+    /// 仓库、车站等辅助建筑，没有配方和工人。
+    Depot {
+        building_type: types::Depot,
+        amount: u8,
+        /// 所在地点，为空时不计运输费用。
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        location: Option<String>,
+    },
+}
+
+impl Condition {
+    /// 按建筑类型构造条件。采集器和农场使用 `outbuilding_amount`，工厂忽略它；
+    /// 辅助建筑只使用 `amount`。
+    pub fn new(
+        building_type: types::Type,
+        recipe_id: Id,
+        worker_wage: building::WorkerWage,
+        outbuilding_amount: building::OutbuildingAmount,
+        amount: u8,
+    ) -> Condition {
+        match building_type {
+            types::Type::Collector(building_type) => Condition::Collector {
+                building_type,
+                recipe_id,
+                worker_wage,
+                collector_amount: outbuilding_amount,
+                amount,
+                location: None,
+            },
+            types::Type::Farm(building_type) => Condition::Farm {
+                building_type,
+                recipe_id,
+                worker_wage,
+                field_amount: outbuilding_amount,
+                amount,
+                location: None,
+            },
+            types::Type::Factory(building_type) => Condition::Factory {
+                building_type,
+                recipe_id,
+                worker_wage,
+                amount,
+                location: None,
+            },
+            types::Type::Depot(building_type) => Condition::Depot {
+                building_type,
+                amount,
+                location: None,
+            },
+        }
+    }
+
+    pub fn building_type(&self) -> types::Type {
+        match self {
+            Condition::Collector { building_type, .. } => types::Type::Collector(*building_type),
+            Condition::Farm { building_type, .. } => types::Type::Farm(*building_type),
+            Condition::Factory { building_type, .. } => types::Type::Factory(*building_type),
+            Condition::Depot { building_type, .. } => types::Type::Depot(*building_type),
+        }
+    }
+
+    /// 使用的配方。辅助建筑没有配方。
+    pub fn recipe_id(&self) -> Option<&Id> {
+        match self {
+            Condition::Collector { recipe_id, .. }
+            | Condition::Farm { recipe_id, .. }
+            | Condition::Factory { recipe_id, .. } => Some(recipe_id),
+            Condition::Depot { .. } => None,
+        }
+    }
+
+    /// 工人工资。辅助建筑没有工人。
+    pub fn worker_wage(&self) -> Option<building::WorkerWage> {
+        match self {
+            Condition::Collector { worker_wage, .. }
+            | Condition::Farm { worker_wage, .. }
+            | Condition::Factory { worker_wage, .. } => Some(*worker_wage),
+            Condition::Depot { .. } => None,
+        }
+    }
+
+    /// 设置工人工资。对辅助建筑无效。
+    pub fn set_worker_wage(&mut self, value: building::WorkerWage) {
+        match self {
+            Condition::Collector { worker_wage, .. }
+            | Condition::Farm { worker_wage, .. }
+            | Condition::Factory { worker_wage, .. } => *worker_wage = value,
+            Condition::Depot { .. } => {}
+        }
+    }
+
+    pub fn amount(&self) -> u8 {
+        match self {
+            Condition::Collector { amount, .. }
+            | Condition::Farm { amount, .. }
+            | Condition::Factory { amount, .. }
+            | Condition::Depot { amount, .. } => *amount,
+        }
+    }
+
+    pub fn set_amount(&mut self, value: u8) {
+        match self {
+            Condition::Collector { amount, .. }
+            | Condition::Farm { amount, .. }
+            | Condition::Factory { amount, .. }
+            | Condition::Depot { amount, .. } => *amount = value,
+        }
+    }
+
+    pub fn location(&self) -> Option<&str> {
+        match self {
+            Condition::Collector { location, .. }
+            | Condition::Farm { location, .. }
+            | Condition::Factory { location, .. }
+            | Condition::Depot { location, .. } => location.as_deref(),
+        }
+    }
+
+    pub fn set_location(&mut self, value: Option<String>) {
+        match self {
+            Condition::Collector { location, .. }
+            | Condition::Farm { location, .. }
+            | Condition::Factory { location, .. }
+            | Condition::Depot { location, .. } => *location = value,
+        }
+    }
+
+    /// 按照条件创建一座建筑。
+    fn create_building(&self) -> Result<Box<dyn building::Building>> {
+        let building: Box<dyn building::Building> = match self {
+            Condition::Collector {
+                building_type,
+                recipe_id,
+                worker_wage,
+                collector_amount,
+                ..
+            } => Box::new(building::CollectorPlant::create(
+                *building_type,
+                *collector_amount,
+                recipe::get(recipe_id)?,
+                *worker_wage,
+            )?),
+            Condition::Farm {
+                building_type,
+                recipe_id,
+                worker_wage,
+                field_amount,
+                ..
+            } => Box::new(building::Farm::create(
+                *building_type,
+                *field_amount,
+                recipe::get(recipe_id)?,
+                *worker_wage,
+            )?),
+            Condition::Factory {
+                building_type,
+                recipe_id,
+                worker_wage,
+                ..
+            } => Box::new(building::Factory::create(
+                *building_type,
+                recipe::get(recipe_id)?,
+                *worker_wage,
+            )?),
+            Condition::Depot { building_type, .. } => {
+                Box::new(building::Depot::create(*building_type)?)
+            }
+        };
+        Ok(building)
+    }
+
+    /// 该条件下所有建筑的总产能。
+    pub fn productivity(&self) -> Result<productivity::Productivity> {
+        Ok(self.create_building()?.productivity()? * self.amount() as f64)
+    }
+
+    /// 该条件下所有建筑的总价格。
+    pub fn price(&self) -> Result<money::Money> {
+        Ok(self.create_building()?.price() * self.amount() as i64)
+    }
+
+    /// 该条件下所有建筑的每月维护费。
+    pub fn upkeep(&self) -> Result<money::Money> {
+        Ok(self.create_building()?.upkeep() * self.amount() as i64)
+    }
+
+    /// 检查该条件能否被模拟，即建筑信息、配方和价格都存在。
+    pub fn check(&self) -> Result<()> {
+        Simulator::from_conditions(std::slice::from_ref(self))?
+            .simulate()
+            .map(|_| ())
+    }
+}
+
+/// 单个条件（条件表中的一行）对报告的贡献。
+#[derive(Debug, Clone)]
+pub struct Contribution {
+    productivity: productivity::Productivity,
+    price: money::Money,
+    upkeep: money::Money,
+    sales: money::Money,         // 按产出比例分摊到该条件的销售额
+    material_cost: money::Money, // 按消耗比例分摊到该条件的原料费
+    internal: money::Money,      // 内部流转按市场价结算的净收入，供应方为正，使用方为负
+    logistics: money::Money,     // 运输费用：运往市场的由产出方、运来的原料由使用方承担
+    utilization: f64,            // 实际开工率，只有限制原料供给时才会小于 1
+}
+
+impl Contribution {
+    pub fn productivity(&self) -> &productivity::Productivity {
+        &self.productivity
+    }
+    pub fn price(&self) -> money::Money {
+        self.price
+    }
+    pub fn upkeep(&self) -> money::Money {
+        self.upkeep
+    }
+    pub fn sales(&self) -> money::Money {
+        self.sales
+    }
+    pub fn material_cost(&self) -> money::Money {
+        self.material_cost
+    }
+    pub fn internal(&self) -> money::Money {
+        self.internal
+    }
+    pub fn utilization(&self) -> f64 {
+        self.utilization
+    }
+    pub fn logistics(&self) -> money::Money {
+        self.logistics
+    }
+    /// 该条件的边际利润：分摊的销售额加内部流转净收入，减去维护费、分摊的原料费和运输费用。
+    /// 各条件的边际利润之和等于报告的月利润（误差在舍入范围内）。
+    pub fn profit(&self) -> money::Money {
+        self.sales + self.internal - self.upkeep - self.material_cost - self.logistics
+    }
 }
 
 #[derive(Debug)]
 // 模拟报告。
 pub struct Report {
     productivity: productivity::Productivity,
+    produced: productivity::Productivity, // 各建筑产出之和
+    consumed: productivity::Productivity, // 各建筑消耗之和（正值）
     total_buildings: HashMap<types::Type, u32>,
     total_price: money::Money,                     // 建筑总价格
     estimated_monthly_upkeep: money::Money,        // 预计每月维护费
     estimated_monthly_material_cost: money::Money, // 预计购买每月原料价格
     estimated_monthly_sales: money::Money,         // 预计月销售额
+    monthly_logistics_cost: money::Money,          // 每月运输费用
+    contributions: Vec<Contribution>,              // 与条件一一对应
+    settings: settings::Settings,                  // 模拟时使用的游戏设置
+    market: market::Market,                        // 模拟时使用的市场设置
+    sales: Vec<market::Sale>,                      // 各剩余物品的销售情况，按物品排序
 }
 
 impl Report {
@@ -92,9 +436,32 @@ impl Report {
     pub fn productivity(&self) -> &productivity::Productivity {
         &self.productivity
     }
+    pub fn produced(&self) -> &productivity::Productivity {
+        &self.produced
+    }
+    pub fn consumed(&self) -> &productivity::Productivity {
+        &self.consumed
+    }
     pub fn total_buildings(&self) -> &HashMap<types::Type, u32> {
         &self.total_buildings
     }
+    /// 模拟时使用的游戏设置。报告中的月度数值按其中的月长计算。
+    pub fn settings(&self) -> &settings::Settings {
+        &self.settings
+    }
+    pub fn month_length(&self) -> f64 {
+        self.settings.month_length
+    }
+    pub fn market(&self) -> &market::Market {
+        &self.market
+    }
+    /// 各剩余物品每月的销售情况，包括卖不出去的数量。
+    pub fn sales(&self) -> &[market::Sale] {
+        &self.sales
+    }
+    pub fn sale(&self, item: recipe::Item) -> Option<&market::Sale> {
+        self.sales.iter().find(|sale| sale.item == item)
+    }
     pub fn monthly_sales(&self) -> money::Money {
         self.estimated_monthly_sales
     }
@@ -105,126 +472,699 @@ impl Report {
         self.estimated_monthly_sales
             - self.estimated_monthly_upkeep
             - self.estimated_monthly_material_cost
+            - self.monthly_logistics_cost
+    }
+    pub fn monthly_logistics_cost(&self) -> money::Money {
+        self.monthly_logistics_cost
     }
     pub fn monthly_material_cost(&self) -> money::Money {
         self.estimated_monthly_material_cost
     }
+    /// 各条件的贡献，顺序与传给 `Simulator::from_conditions` 的条件相同。
+    pub fn contributions(&self) -> &[Contribution] {
+        &self.contributions
+    }
     pub fn profit_rate(&self) -> f64 {
         self.monthly_profit().value() as f64 / self.estimated_monthly_sales.value() as f64
     }
+    /// 月投资回报率，即月利润与建筑总价格之比。建筑总价格为零时为 `None`。
+    pub fn monthly_roi(&self) -> Option<f64> {
+        let total_price = self.total_price.value();
+        (total_price != 0).then(|| self.monthly_profit().value() as f64 / total_price as f64)
+    }
+    /// 回本所需月数。不盈利时为 `None`。
+    pub fn payback_months(&self) -> Option<f64> {
+        let profit = self.monthly_profit().value();
+        (profit > 0).then(|| self.total_price.value() as f64 / profit as f64)
+    }
+    /// 从第 0 个月（投入 `total_price`）起，每月末的累计现金流，共 `months + 1` 项。
+    pub fn cash_flow(&self, months: u32) -> Vec<money::Money> {
+        (0..=months as i64)
+            .map(|month| self.monthly_profit() * month - self.total_price)
+            .collect()
+    }
+    /// 展示现金流时使用的月数：回本时间之后再多一年，至少两年，最多十年。
+    pub fn cash_flow_horizon(&self) -> u32 {
+        self.payback_months()
+            .map_or(24, |months| (months.ceil() as u32).saturating_add(12))
+            .clamp(24, 120)
+    }
+}
+
+/// 模拟选项。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    /// 不从市场购买原料。原料不足的建筑按实际能得到的原料比例降低开工率，并影响下游建筑。
+    pub throttle: bool,
 }
 
 pub struct Simulator {
-    buildings: Vec<Box<dyn building::Building>>,
+    /// 每个条件对应的建筑。
+    groups: Vec<Vec<Box<dyn building::Building>>>,
+    /// 每个条件所在的地点。
+    locations: Vec<Option<String>>,
+    logistics: logistics::Transport,
+    settings: settings::Settings,
+    market: market::Market,
+    options: Options,
+    /// 限制供给时仍从市场购买的原料。
+    bought_inputs: BTreeSet<recipe::Item>,
 }
 
 impl Simulator {
     pub fn from_conditions(conditions: &[Condition]) -> Result<Self> {
-        let mut buildings: Vec<Box<dyn building::Building>> = vec![];
+        let mut groups = vec![];
+        let mut locations = vec![];
         for cond in conditions {
-            match cond {
-                Condition::Collector {
-                    building_type,
-                    recipe_id,
-                    worker_wage,
-                    collector_amount,
-                    amount,
-                } => {
-                    for _ in 0..*amount {
-                        buildings.push(Box::new(building::CollectorPlant::create(
-                            *building_type,
-                            *collector_amount,
-                            recipe::get(recipe_id),
-                            *worker_wage,
-                        )?));
-                    }
-                }
-                Condition::Farm {
-                    building_type,
-                    recipe_id,
-                    worker_wage,
-                    field_amount,
-                    amount,
-                } => {
-                    for _ in 0..*amount {
-                        buildings.push(Box::new(building::Farm::create(
-                            *building_type,
-                            *field_amount,
-                            recipe::get(recipe_id),
-                            *worker_wage,
-                        )?))
-                    }
-                }
-                Condition::Factory {
-                    building_type,
-                    recipe_id,
-                    worker_wage,
-                    amount,
-                } => {
-                    for _ in 0..*amount {
-                        buildings.push(Box::new(building::Factory::create(
-                            *building_type,
-                            recipe::get(recipe_id),
-                            *worker_wage,
-                        )?))
+            locations.push(cond.location().map(str::to_owned));
+            let mut buildings: Vec<Box<dyn building::Building>> = vec![];
+            for _ in 0..cond.amount() {
+                buildings.push(cond.create_building()?);
+            }
+            groups.push(buildings);
+        }
+        Ok(Simulator {
+            groups,
+            locations,
+            logistics: logistics::Transport::default(),
+            settings: settings::Settings::default(),
+            market: market::Market::default(),
+            options: Options::default(),
+            bought_inputs: BTreeSet::new(),
+        })
+    }
+
+    /// 按规划的条件、运输设置、游戏设置和市场设置创建。
+    pub fn for_plan(plan: &plan::Plan) -> Result<Self> {
+        Ok(Self::from_conditions(&plan.conditions)?
+            .with_logistics(plan.logistics.clone())
+            .with_settings(plan.settings.clone())
+            .with_market(plan.market.clone()))
+    }
+
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// 按规划的运输设置计算运输费用。
+    pub fn with_logistics(mut self, logistics: logistics::Transport) -> Self {
+        self.logistics = logistics;
+        self
+    }
+
+    /// 按游戏设置调整建造费用、维护费、市场价格、生产速度和月长。
+    pub fn with_settings(mut self, settings: settings::Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// 按城镇需求出售剩余物品，而不是全部按市场价卖出。
+    pub fn with_market(mut self, market: market::Market) -> Self {
+        self.market = market;
+        self
+    }
+
+    /// 限制供给时，`items` 仍按市场价购买，不限制使用它们的建筑的开工率。
+    pub fn with_bought_inputs(mut self, items: BTreeSet<recipe::Item>) -> Self {
+        self.bought_inputs = items;
+        self
+    }
+
+    /// 按设置调整生产速度后的建筑产能。
+    fn building_productivity(
+        &self,
+        building: &dyn building::Building,
+    ) -> Result<productivity::Productivity> {
+        let category = settings::Category::from(building.plant_type());
+        Ok(building.productivity()? * self.settings.speed.get(category))
+    }
+
+    /// 各条件的开工率。不限制原料供给时都为 1。
+    ///
+    /// 限制供给时反复迭代：每种原料的供给按需求比例分给各使用者，使用者的开工率由最缺的原料决定，
+    /// 直到开工率不再变化。
+    fn utilization(&self) -> Result<Vec<f64>> {
+        let mut utilization = vec![1.0; self.groups.len()];
+        if !self.options.throttle {
+            return Ok(utilization);
+        }
+        let full = self
+            .groups
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|b| self.building_productivity(b.as_ref()))
+                    .sum()
+            })
+            .collect::<Result<Vec<productivity::Productivity>>>()?;
+        const MAX_ITERATIONS: usize = 1000;
+        for _ in 0..MAX_ITERATIONS {
+            let mut supply: HashMap<recipe::Item, f64> = HashMap::new();
+            let mut demand: HashMap<recipe::Item, f64> = HashMap::new();
+            for (prod, u) in full.iter().zip(utilization.iter()) {
+                for (item, speed) in prod.iter() {
+                    let rate = speed.per_month(self.settings.month_length) * u;
+                    if rate > 0.0 {
+                        *supply.entry(*item).or_insert(0.0) += rate;
+                    } else {
+                        *demand.entry(*item).or_insert(0.0) -= rate;
                     }
                 }
             }
+            let mut change: f64 = 0.0;
+            for (prod, u) in full.iter().zip(utilization.iter_mut()) {
+                let ratio = prod
+                    .iter()
+                    .filter(|(item, speed)| {
+                        speed.per_month(self.settings.month_length) < 0.0
+                            && !self.bought_inputs.contains(item)
+                    })
+                    .map(|(item, _)| {
+                        let demand = demand.get(item).copied().unwrap_or(0.0);
+                        if demand > 0.0 {
+                            supply.get(item).copied().unwrap_or(0.0) / demand
+                        } else {
+                            1.0
+                        }
+                    })
+                    .fold(f64::INFINITY, f64::min);
+                let next = (*u * ratio).min(1.0);
+                change = change.max((next - *u).abs());
+                *u = next;
+            }
+            if change < 1e-12 {
+                break;
+            }
         }
-        // buildings.push(building);
-        Ok(Simulator { buildings })
+        Ok(utilization)
     }
 
-    pub fn simulate(&self) -> Report {
+    pub fn simulate(&self) -> Result<Report> {
+        self.settings.check()?;
+        self.market.check()?;
         let mut productivity: productivity::Productivity =
             productivity::Productivity::new(HashMap::new());
+        let mut produced = productivity::Productivity::new(HashMap::new());
+        let mut consumed = productivity::Productivity::new(HashMap::new());
         let mut total_buildings: HashMap<types::Type, u32> = HashMap::new();
         let mut total_price = money::Money::zero();
         let mut estimated_monthly_upkeep = money::Money::zero();
-        for i in self.buildings.iter() {
-            let plant_type = i.plant_type();
-            let prod = i.productivity();
-            total_price += i.price();
-            estimated_monthly_upkeep += i.upkeep();
-            productivity += prod;
-            total_buildings
-                .entry(plant_type)
-                .and_modify(|amount| *amount += 1)
-                .or_insert(1);
-        }
-        let estimated_monthly_sales = productivity.estimated_monthly_sales();
-        let estimated_monthly_material_cost = productivity.estimated_monthly_material_cost();
-        Report {
+        let mut contributions = vec![];
+        let utilization = self.utilization()?;
+        for (group, u) in self.groups.iter().zip(utilization) {
+            let mut contribution = Contribution {
+                productivity: productivity::Productivity::new(HashMap::new()),
+                price: money::Money::zero(),
+                upkeep: money::Money::zero(),
+                sales: money::Money::zero(),
+                material_cost: money::Money::zero(),
+                internal: money::Money::zero(),
+                logistics: money::Money::zero(),
+                utilization: u,
+            };
+            for i in group.iter() {
+                let plant_type = i.plant_type();
+                let category = settings::Category::from(plant_type);
+                let prod = self.building_productivity(i.as_ref())? * u;
+                contribution.price += i.price() * self.settings.construction.get(category);
+                contribution.upkeep += i.upkeep() * self.settings.upkeep.get(category);
+                contribution.productivity += prod.clone();
+                let (outputs, inputs) = prod.split();
+                produced += outputs;
+                consumed += inputs;
+                productivity += prod;
+                total_buildings
+                    .entry(plant_type)
+                    .and_modify(|amount| *amount += 1)
+                    .or_insert(1);
+            }
+            total_price += contribution.price;
+            estimated_monthly_upkeep += contribution.upkeep;
+            contributions.push(contribution);
+        }
+        let mut sales = vec![];
+        for (item, speed) in productivity.iter() {
+            let net = speed.per_month(self.settings.month_length);
+            if net > 0.0 {
+                let price = self.settings.price(*item)?.value() as f64;
+                sales.push(self.market.sell(*item, net, price));
+            }
+        }
+        sales.sort_by_key(|sale| sale.item);
+        // 不启用市场模型时按精确速率计算，避免逐项舍入的误差
+        let estimated_monthly_sales = if self.market.enabled() {
+            money::Money::from(sales.iter().map(|sale| sale.revenue).sum::<f64>().round() as i64)
+        } else {
+            productivity.estimated_monthly_sales(&self.settings)?
+        };
+        let estimated_monthly_material_cost =
+            productivity.estimated_monthly_material_cost(&self.settings)?;
+        self.share_out(
+            &mut contributions,
+            &productivity,
+            &produced,
+            &consumed,
+            &sales,
+        )?;
+        let monthly_logistics_cost = contributions
+            .iter()
+            .fold(money::Money::zero(), |sum, c| sum + c.logistics);
+        Ok(Report {
             productivity,
+            produced,
+            consumed,
             total_buildings,
             total_price,
             estimated_monthly_upkeep,
             estimated_monthly_sales,
             estimated_monthly_material_cost,
+            monthly_logistics_cost,
+            contributions,
+            settings: self.settings.clone(),
+            market: self.market.clone(),
+            sales,
+        })
+    }
+
+    /// 把剩余物品的销售额按产出比例、不足物品的购买费用按消耗比例分摊到各条件。
+    /// 自给的部分按市场价由使用方付给供应方，同样按比例分摊。
+    ///
+    /// 运输量也按同样的比例分配：每个供应方按比例供给每个使用方，卖出的部分运往市场或各城镇，不足的从市场运来。
+    fn share_out(
+        &self,
+        contributions: &mut [Contribution],
+        total: &productivity::Productivity,
+        produced: &productivity::Productivity,
+        consumed: &productivity::Productivity,
+        item_sales: &[market::Sale],
+    ) -> Result<()> {
+        let mut sales = vec![0.0; contributions.len()];
+        let mut material_cost = vec![0.0; contributions.len()];
+        let mut internal = vec![0.0; contributions.len()];
+        let mut logistics = vec![0.0; contributions.len()];
+        let market = self.logistics.market.as_deref();
+        let monthly = |speed: productivity::Speed| speed.per_month(self.settings.month_length);
+        for (item, speed) in total.iter() {
+            let price = self.settings.price(*item)?.value() as f64;
+            let net = monthly(*speed);
+            let produced = produced.get(*item).map_or(0.0, monthly);
+            let consumed = consumed.get(*item).map_or(0.0, monthly);
+            let self_supplied = produced.min(consumed);
+            let parts = contributions
+                .iter()
+                .map(|c| c.productivity.get(*item).map_or(0.0, monthly))
+                .collect::<Vec<_>>();
+            for (i, part) in parts.iter().enumerate() {
+                let location = self.locations[i].as_deref();
+                if *part > 0.0 {
+                    let ratio = part / produced;
+                    internal[i] += price * self_supplied * ratio;
+                    let Some(sale) = item_sales.iter().find(|sale| sale.item == *item) else {
+                        continue;
+                    };
+                    sales[i] += sale.revenue * ratio;
+                    if sale.towns.is_empty() {
+                        logistics[i] +=
+                            self.logistics
+                                .monthly_cost(location, market, sale.sold * ratio)?;
+                    }
+                    for town in sale.towns.iter() {
+                        let destination = self
+                            .market
+                            .town(&town.town)
+                            .and_then(|t| t.location.as_deref())
+                            .or(market);
+                        logistics[i] += self.logistics.monthly_cost(
+                            location,
+                            destination,
+                            town.sold * ratio,
+                        )?;
+                    }
+                } else if *part < 0.0 {
+                    let ratio = -part / consumed;
+                    material_cost[i] += price * (-net).max(0.0) * ratio;
+                    internal[i] -= price * self_supplied * ratio;
+                    logistics[i] +=
+                        self.logistics
+                            .monthly_cost(market, location, (-net).max(0.0) * ratio)?;
+                    for (j, supply) in parts.iter().enumerate() {
+                        if *supply > 0.0 {
+                            logistics[i] += self.logistics.monthly_cost(
+                                self.locations[j].as_deref(),
+                                location,
+                                self_supplied * ratio * supply / produced,
+                            )?;
+                        }
+                    }
+                }
+            }
         }
+        for (i, contribution) in contributions.iter_mut().enumerate() {
+            contribution.sales = money::Money::from(sales[i].round() as i64);
+            contribution.material_cost = money::Money::from(material_cost[i].round() as i64);
+            contribution.internal = money::Money::from(internal[i].round() as i64);
+            contribution.logistics = money::Money::from(logistics[i].round() as i64);
+        }
+        Ok(())
     }
 }
 
+/// 测试用的生产链：一座带五个采集点的伐木场和一座木工中心。伐木场的木材有剩余。
+#[cfg(test)]
+fn wood_chain() -> Vec<Condition> {
+    vec![
+        Condition::new(
+            types::Type::Collector(types::Collector::Lumberyard),
+            Id(String::from("Wood")),
+            building::WorkerWage::PERCENT_100,
+            building::OutbuildingAmount::Five,
+            1,
+        ),
+        Condition::new(
+            types::Type::Factory(types::Factory::CarpentryCenter),
+            Id(String::from("WoodenPlanks")),
+            building::WorkerWage::PERCENT_100,
+            building::OutbuildingAmount::Five,
+            1,
+        ),
+    ]
+}
+
 #[test]
 fn data_intergration_test() {
-    self::building::info::INFOS.iter().for_each(|i| match i {
+    self::building::info::infos().iter().for_each(|i| match i {
         (types::Type::Collector(_), building::info::Info::Collector(x)) => x
             .recipes
             .iter()
-            .for_each(|id| println!("{:?}", self::recipe::get(id))),
+            .for_each(|id| println!("{:?}", self::recipe::get(id).unwrap())),
         (types::Type::Collector(_), building::info::Info::Farm(_)) => unreachable!(),
         (types::Type::Collector(_), building::info::Info::Factory(_)) => unreachable!(),
         (types::Type::Farm(_), building::info::Info::Collector(_)) => unreachable!(),
         (types::Type::Farm(_), building::info::Info::Farm(x)) => x
             .recipes
             .iter()
-            .for_each(|id| println!("{:?}", self::recipe::get(id))),
+            .for_each(|id| println!("{:?}", self::recipe::get(id).unwrap())),
         (types::Type::Farm(_), building::info::Info::Factory(_)) => unreachable!(),
         (types::Type::Factory(_), building::info::Info::Collector(_)) => unreachable!(),
         (types::Type::Factory(_), building::info::Info::Farm(_)) => unreachable!(),
         (types::Type::Factory(_), building::info::Info::Factory(x)) => x
             .recipes
             .iter()
-            .for_each(|id| println!("{:?}", self::recipe::get(id))),
+            .for_each(|id| println!("{:?}", self::recipe::get(id).unwrap())),
+        (_, building::info::Info::Depot(_)) | (types::Type::Depot(_), _) => {
+            assert!(i.1.matches(*i.0))
+        }
+    });
+}
+
+#[test]
+fn condition_check_test() {
+    let cond = Condition::Factory {
+        building_type: types::Factory::ToyFactory,
+        recipe_id: Id(String::from("NoSuchRecipe")),
+        worker_wage: building::WorkerWage::PERCENT_100,
+        amount: 1,
+        location: None,
+    };
+    let Err(e) = cond.check() else { panic!() };
+    assert!(matches!(*e.0, ErrorImpl::UnknownRecipe(_)));
+    assert_eq!(e.to_string(), "unknown recipe \"NoSuchRecipe\"");
+}
+
+#[test]
+fn payback_test() {
+    let report = Simulator::from_conditions(&wood_chain())
+        .unwrap()
+        .simulate()
+        .unwrap();
+    let months = report.payback_months().unwrap();
+    let cash_flow = report.cash_flow(months.ceil() as u32);
+    assert_eq!(cash_flow[0], money::Money::zero() - report.total_price());
+    assert!(cash_flow[cash_flow.len() - 2] < money::Money::zero());
+    assert!(*cash_flow.last().unwrap() >= money::Money::zero());
+    assert!((report.monthly_roi().unwrap() * months - 1.0).abs() < 1e-9);
+
+    // 没有建筑时回报率无从计算，现金流展示两年
+    let empty = Simulator::from_conditions(&[]).unwrap().simulate().unwrap();
+    assert_eq!(empty.monthly_roi(), None);
+    assert_eq!(empty.cash_flow_horizon(), 24);
+}
+
+#[test]
+fn contribution_test() {
+    // 三座木工中心用光伐木场的全部木材
+    let mut conditions = wood_chain();
+    conditions[1].set_amount(3);
+    let report = Simulator::from_conditions(&conditions)
+        .unwrap()
+        .simulate()
+        .unwrap();
+    let [lumberyard, carpentry] = report.contributions() else {
+        panic!()
+    };
+    // 木材全部被木工中心消耗，伐木场没有销售额，只有内部流转收入
+    assert_eq!(lumberyard.sales(), money::Money::zero());
+    assert_eq!(lumberyard.material_cost(), money::Money::zero());
+    assert!(lumberyard.internal() > money::Money::zero());
+    assert_eq!(
+        lumberyard.internal() + carpentry.internal(),
+        money::Money::zero()
+    );
+    assert_eq!(carpentry.sales(), report.monthly_sales());
+    assert_eq!(carpentry.material_cost(), report.monthly_material_cost());
+    assert_eq!(lumberyard.price() + carpentry.price(), report.total_price());
+    let profit = lumberyard.profit() + carpentry.profit();
+    assert!((profit - report.monthly_profit()).value().abs() <= 1);
+}
+
+#[test]
+fn throttle_test() {
+    // 一座伐木场供不上三座木工中心
+    let mut conditions = wood_chain();
+    conditions[1].set_amount(3);
+    let report = Simulator::from_conditions(&conditions)
+        .unwrap()
+        .with_options(Options { throttle: true })
+        .simulate()
+        .unwrap();
+    let [lumberyard, carpentry] = report.contributions() else {
+        panic!()
+    };
+    assert_eq!(lumberyard.utilization(), 1.0);
+    let wood = lumberyard.productivity().get(recipe::Item::Wood).unwrap();
+    let used = carpentry.productivity().get(recipe::Item::Wood).unwrap();
+    assert!(
+        (wood.per_month(report.month_length()) + used.per_month(report.month_length())).abs()
+            < 1e-6
+    );
+    assert!(carpentry.utilization() < 1.0);
+    assert_eq!(report.monthly_material_cost(), money::Money::zero());
+}
+
+#[test]
+fn logistics_report_test() {
+    use logistics::{Route, Transport};
+    let mut conditions = wood_chain();
+    let free = Simulator::from_conditions(&conditions)
+        .unwrap()
+        .simulate()
+        .unwrap();
+    assert_eq!(free.monthly_logistics_cost(), money::Money::zero());
+
+    conditions[0].set_location(Some(String::from("Forest")));
+    conditions[1].set_location(Some(String::from("Mill")));
+    let route = |from: &str, to: &str, distance| Route {
+        from: String::from(from),
+        to: String::from(to),
+        distance,
+        vehicle: None,
+    };
+    let logistics = Transport {
+        market: Some(String::from("City")),
+        routes: vec![
+            route("Forest", "Mill", 10.0),
+            route("Forest", "City", 30.0),
+            route("Mill", "City", 20.0),
+        ],
+        ..Default::default()
+    };
+    let report = Simulator::from_conditions(&conditions)
+        .unwrap()
+        .with_logistics(logistics.clone())
+        .simulate()
+        .unwrap();
+    let cost = report.monthly_logistics_cost();
+    assert!(cost > money::Money::zero());
+    assert_eq!(report.monthly_profit(), free.monthly_profit() - cost);
+    let sum = report
+        .contributions()
+        .iter()
+        .fold(money::Money::zero(), |sum, c| sum + c.profit());
+    assert!((sum - report.monthly_profit()).value().abs() <= 2);
+
+    // 缺少路线时报错
+    let no_route = Transport {
+        routes: vec![route("Forest", "Mill", 10.0)],
+        ..logistics
+    };
+    assert!(Simulator::from_conditions(&conditions)
+        .unwrap()
+        .with_logistics(no_route)
+        .simulate()
+        .is_err());
+}
+
+#[test]
+fn depot_test() {
+    let chain = wood_chain();
+    let warehouse = Condition::Depot {
+        building_type: types::Depot::Warehouse,
+        amount: 2,
+        location: None,
+    };
+    assert_eq!(warehouse.recipe_id(), None);
+    assert_eq!(warehouse.worker_wage(), None);
+    warehouse.check().unwrap();
+    let alone = Simulator::from_conditions(&chain)
+        .unwrap()
+        .simulate()
+        .unwrap();
+    let with_warehouse = chain.iter().cloned().chain([warehouse]).collect::<Vec<_>>();
+    let report = Simulator::from_conditions(&with_warehouse)
+        .unwrap()
+        .simulate()
+        .unwrap();
+    let info = building::info::get_depot_info(types::Depot::Warehouse).unwrap();
+    assert_eq!(report.total_price(), alone.total_price() + info.price * 2);
+    assert_eq!(
+        report.monthly_upkeep(),
+        alone.monthly_upkeep() + info.upkeep * 2
+    );
+    assert_eq!(report.monthly_sales(), alone.monthly_sales());
+    assert_eq!(
+        report.total_buildings()[&types::Type::Depot(types::Depot::Warehouse)],
+        2
+    );
+    assert_eq!(
+        report.contributions()[2].profit(),
+        money::Money::zero() - info.upkeep * 2
+    );
+}
+
+#[test]
+fn settings_report_test() {
+    use settings::{Category, Multiplier, PriceMultiplier, Settings};
+    use std::collections::BTreeMap;
+    let conditions = wood_chain();
+    let simulate = |settings: Settings| {
+        Simulator::from_conditions(&conditions)
+            .unwrap()
+            .with_settings(settings)
+            .simulate()
+            .unwrap()
+    };
+    let base = simulate(Settings::default());
+    let planks = |report: &Report| {
+        report
+            .productivity()
+            .get(recipe::Item::WoodenPlanks)
+            .unwrap()
+            .per_month(report.month_length())
+    };
+
+    // 月长加倍时月产量和月销售额加倍，维护费不变
+    let long = simulate(Settings {
+        month_length: 60.0,
+        ..Default::default()
     });
+    assert!((planks(&long) - planks(&base) * 2.0).abs() < 1e-6);
+    assert_eq!(long.monthly_upkeep(), base.monthly_upkeep());
+    assert_eq!(long.total_price(), base.total_price());
+
+    let settings = Settings {
+        construction: Multiplier {
+            global: 2.0,
+            ..Default::default()
+        },
+        upkeep: Multiplier {
+            global: 1.0,
+            categories: BTreeMap::from([(Category::Factory, 0.5)]),
+        },
+        speed: Multiplier {
+            global: 1.5,
+            ..Default::default()
+        },
+        market: PriceMultiplier {
+            global: 2.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let report = simulate(settings);
+    assert_eq!(report.total_price(), base.total_price() * 2);
+    let [lumberyard, carpentry] = report.contributions() else {
+        panic!()
+    };
+    assert_eq!(lumberyard.upkeep(), base.contributions()[0].upkeep());
+    assert_eq!(carpentry.upkeep(), base.contributions()[1].upkeep() * 0.5);
+    assert!((planks(&report) - planks(&base) * 1.5).abs() < 1e-6);
+    let sales = base.monthly_sales().value() as f64 * 1.5 * 2.0;
+    assert!((report.monthly_sales().value() as f64 - sales).abs() <= 1.0);
+
+    let invalid = Simulator::from_conditions(&conditions)
+        .unwrap()
+        .with_settings(Settings {
+            month_length: -1.0,
+            ..Default::default()
+        })
+        .simulate();
+    assert!(invalid.is_err());
+}
+
+#[test]
+fn market_report_test() {
+    use market::{Market, Town};
+    use std::collections::BTreeMap;
+    let conditions = wood_chain();
+    let simulate = |market: Market| {
+        Simulator::from_conditions(&conditions)
+            .unwrap()
+            .with_market(market)
+            .simulate()
+            .unwrap()
+    };
+    let base = simulate(Market::default());
+    let planks = base.sale(recipe::Item::WoodenPlanks).unwrap();
+    assert_eq!(planks.unsold(), 0.0);
+
+    // 需求只有产量的四分之一时，超出需求两倍的部分卖不出去
+    let town = Town {
+        name: String::from("A"),
+        location: None,
+        demand: BTreeMap::from([(recipe::Item::WoodenPlanks, planks.offered / 4.0)]),
+    };
+    let small = simulate(Market {
+        towns: vec![town],
+        ..Default::default()
+    });
+    let sale = small.sale(recipe::Item::WoodenPlanks).unwrap();
+    assert!((sale.unsold() - planks.offered / 2.0).abs() < 1e-6);
+    assert!(small.monthly_sales() < base.monthly_sales());
+    let contributed = small
+        .contributions()
+        .iter()
+        .fold(money::Money::zero(), |sum, c| sum + c.sales);
+    assert!((contributed - small.monthly_sales()).value().abs() <= 2);
+
+    let bad = Market {
+        destinations: BTreeMap::from([(recipe::Item::WoodenPlanks, String::from("B"))]),
+        ..Default::default()
+    };
+    assert!(Simulator::from_conditions(&conditions)
+        .unwrap()
+        .with_market(bad)
+        .simulate()
+        .is_err());
 }
@@ -19,7 +19,7 @@ use std::{
 
 use serde_derive::Deserialize;
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Deserialize, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Deserialize, Debug, Default)]
 pub struct Money(i64);
 
 impl Money {
@@ -31,14 +31,14 @@ impl Money {
 impl Display for Money {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str = match self.0 {
-            i64::MIN..=-1_000_000 => format!("-${:.2}M", -self.0 as f64 / 1_000_000 as f64),
-            -999_999..=-1_000 => format!("-${:.2}K", -self.0 as f64 / 1_000 as f64),
+            i64::MIN..=-1_000_000 => format!("-${:.2}M", -self.0 as f64 / 1_000_000.0),
+            -999_999..=-1_000 => format!("-${:.2}K", -self.0 as f64 / 1_000.0),
             -999..=-1 => format!("-${}", -self.0),
             0..=999 => format!("${}", self.0),
-            1_000..=999_999 => format!("${:.2}K", self.0 as f64 / 1_000 as f64),
-            1_000_000..=i64::MAX => format!("${:.2}M", self.0 as f64 / 1_000_000 as f64),
+            1_000..=999_999 => format!("${:.2}K", self.0 as f64 / 1_000.0),
+            1_000_000..=i64::MAX => format!("${:.2}M", self.0 as f64 / 1_000_000.0),
         };
-        write!(f, "{}", str)
+        f.pad(&str)
     }
 }
 
@@ -0,0 +1,267 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 蒙特卡洛模拟：按给定分布随机抽取物品价格和各条件的生产速度，多次计算规划的月利润和回本时间。
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+
+use super::{money::Money, recipe::Item, ErrorImpl, Report, Result};
+
+/// 围绕基准值的随机波动，参数都是相对于基准值的比例。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Noise {
+    None,
+    /// 在 `[1 - x, 1 + x]` 内均匀分布。
+    Uniform(f64),
+    /// 均值为 1、标准差为 `x` 的正态分布。
+    Normal(f64),
+}
+
+impl Noise {
+    /// 波动幅度必须是有限的非负数，否则无法抽样。
+    pub fn check(&self) -> Result<()> {
+        match *self {
+            Noise::None => Ok(()),
+            Noise::Uniform(x) | Noise::Normal(x) if x.is_finite() && x >= 0.0 => Ok(()),
+            Noise::Uniform(x) | Noise::Normal(x) => {
+                Err(ErrorImpl::InvalidNoise(format!("{} is not a valid spread", x)).into())
+            }
+        }
+    }
+
+    /// 抽取一个倍率，不小于零。
+    fn sample<R: Rng>(&self, rng: &mut R) -> f64 {
+        let factor = match *self {
+            Noise::None => 1.0,
+            Noise::Uniform(x) if x > 0.0 => rng.gen_range(1.0 - x..=1.0 + x),
+            Noise::Normal(x) if x > 0.0 => Normal::new(1.0, x).map_or(1.0, |d| d.sample(rng)),
+            Noise::Uniform(_) | Noise::Normal(_) => 1.0,
+        };
+        factor.max(0.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub runs: u32,
+    /// 随机数种子。相同的种子和配置得到相同的结果。
+    pub seed: u64,
+    /// 每种物品价格的波动，各物品独立抽取。
+    pub price: Noise,
+    /// 每个条件生产速度的波动，各条件独立抽取。
+    pub speed: Noise,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            runs: 1000,
+            seed: 0,
+            price: Noise::Normal(0.1),
+            speed: Noise::None,
+        }
+    }
+}
+
+/// 多次模拟的结果。
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    profits: Vec<f64>,  // 每月利润，从小到大排列
+    paybacks: Vec<f64>, // 回本月数，从小到大排列，不盈利时为无穷大
+}
+
+/// 取已排序数据的第 `p` 分位数（`p` 在 0 到 1 之间）。没有数据时为 `None`。
+fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    let last = sorted.len().checked_sub(1)?;
+    let index = (p.clamp(0.0, 1.0) * last as f64).round() as usize;
+    sorted.get(index).copied()
+}
+
+impl Outcome {
+    pub fn runs(&self) -> usize {
+        self.profits.len()
+    }
+    /// 各次模拟的月利润，从小到大排列。
+    pub fn profits(&self) -> &[f64] {
+        &self.profits
+    }
+    pub fn mean_profit(&self) -> Money {
+        let mean = self.profits.iter().sum::<f64>() / self.profits.len().max(1) as f64;
+        Money::from(mean.round() as i64)
+    }
+    pub fn profit_percentile(&self, p: f64) -> Money {
+        // `run` 至少模拟一次，分位数总是存在
+        Money::from(percentile(&self.profits, p).unwrap_or(0.0).round() as i64)
+    }
+    /// 月利润为负的概率。
+    pub fn loss_probability(&self) -> f64 {
+        let losses = self.profits.iter().filter(|profit| **profit < 0.0).count();
+        losses as f64 / self.profits.len().max(1) as f64
+    }
+    /// 回本月数的第 `p` 分位数。该分位上的模拟不盈利时为 `None`。
+    pub fn payback_percentile(&self, p: f64) -> Option<f64> {
+        percentile(&self.paybacks, p).filter(|months| months.is_finite())
+    }
+}
+
+/// 以报告中的各条件产能为基准，按 `config` 随机模拟 `config.runs` 次。次数为零时返回错误。
+pub fn run(report: &Report, config: &Config) -> Result<Outcome> {
+    if config.runs == 0 {
+        return Err(ErrorImpl::NoRuns.into());
+    }
+    config.price.check()?;
+    config.speed.check()?;
+    let items = report
+        .contributions()
+        .iter()
+        .flat_map(|c| c.productivity().iter().map(|(item, _)| *item))
+        .collect::<BTreeSet<Item>>();
+    let mut prices = BTreeMap::new();
+    for item in items {
+        prices.insert(item, report.settings().price(item)?.value() as f64);
+    }
+    let upkeep = report.monthly_upkeep().value() as f64;
+    let total_price = report.total_price().value() as f64;
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut profits = vec![];
+    let mut paybacks = vec![];
+    for _ in 0..config.runs {
+        let price_factors = prices
+            .keys()
+            .map(|item| (*item, config.price.sample(&mut rng)))
+            .collect::<BTreeMap<_, _>>();
+        let mut net: BTreeMap<Item, f64> = BTreeMap::new();
+        // 各条件的运输费用与其运量成正比，按该条件的速度倍率缩放
+        let mut logistics = 0.0;
+        for contribution in report.contributions() {
+            let factor = config.speed.sample(&mut rng);
+            for (item, speed) in contribution.productivity().iter() {
+                *net.entry(*item).or_insert(0.0) += speed.per_month(report.month_length()) * factor;
+            }
+            logistics += contribution.logistics().value() as f64 * factor;
+        }
+        // 与 `Simulator` 相同：剩余的按市场设置出售，不足的按市场价购买
+        let trade = net
+            .iter()
+            .map(|(item, amount)| {
+                let price = prices[item] * price_factors[item];
+                if *amount > 0.0 {
+                    report.market().sell(*item, *amount, price).revenue
+                } else {
+                    price * amount
+                }
+            })
+            .sum::<f64>();
+        let profit = trade - upkeep - logistics;
+        profits.push(profit);
+        paybacks.push(if profit > 0.0 {
+            total_price / profit
+        } else {
+            f64::INFINITY
+        });
+    }
+    profits.sort_by(f64::total_cmp);
+    paybacks.sort_by(f64::total_cmp);
+    Ok(Outcome { profits, paybacks })
+}
+
+#[test]
+fn monte_carlo_test() {
+    use super::{
+        logistics::{Route, Transport},
+        wood_chain, Simulator,
+    };
+    let conditions = wood_chain();
+    let report = Simulator::from_conditions(&conditions)
+        .unwrap()
+        .simulate()
+        .unwrap();
+
+    let fixed = Config {
+        runs: 10,
+        price: Noise::None,
+        ..Default::default()
+    };
+    let outcome = run(&report, &fixed).unwrap();
+    assert!(
+        (outcome.mean_profit() - report.monthly_profit())
+            .value()
+            .abs()
+            <= 1
+    );
+    assert_eq!(outcome.loss_probability(), 0.0);
+
+    let config = Config {
+        runs: 500,
+        seed: 42,
+        price: Noise::Normal(0.3),
+        speed: Noise::Uniform(0.2),
+    };
+    let a = run(&report, &config).unwrap();
+    let b = run(&report, &config).unwrap();
+    assert_eq!(a.profits(), b.profits());
+    assert_eq!(a.runs(), 500);
+    assert!(a.profit_percentile(0.05) <= a.profit_percentile(0.5));
+    assert!(a.profit_percentile(0.5) <= a.profit_percentile(0.95));
+    assert!((0.0..=1.0).contains(&a.loss_probability()));
+    let other = run(&report, &Config { seed: 7, ..config }).unwrap();
+    assert_ne!(a.profits(), other.profits());
+
+    for bad in [f64::INFINITY, f64::NAN, -0.1] {
+        let config = Config {
+            speed: Noise::Uniform(bad),
+            ..config
+        };
+        assert!(run(&report, &config).is_err());
+    }
+    assert!(run(&report, &Config { runs: 0, ..config }).is_err());
+
+    // 有运输费用时，没有波动的平均利润仍等于报告的利润
+    let mut conditions = conditions;
+    conditions[0].set_location(Some(String::from("Forest")));
+    conditions[1].set_location(Some(String::from("Mill")));
+    let route = |from: &str, to: &str, distance| Route {
+        from: String::from(from),
+        to: String::from(to),
+        distance,
+        vehicle: None,
+    };
+    let transport = Transport {
+        market: Some(String::from("City")),
+        routes: vec![
+            route("Forest", "Mill", 10.0),
+            route("Forest", "City", 30.0),
+            route("Mill", "City", 20.0),
+        ],
+        ..Default::default()
+    };
+    let report = Simulator::from_conditions(&conditions)
+        .unwrap()
+        .with_logistics(transport)
+        .simulate()
+        .unwrap();
+    assert!(report.monthly_logistics_cost() > Money::zero());
+    let outcome = run(&report, &fixed).unwrap();
+    assert!(
+        (outcome.mean_profit() - report.monthly_profit())
+            .value()
+            .abs()
+            <= 1
+    );
+    assert_eq!(percentile(&[], 0.5), None);
+}
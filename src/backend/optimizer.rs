@@ -0,0 +1,377 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 在资金预算内搜索利润或投资回报率最高的建筑组合。
+//!
+//! 先为每种建筑和配方挑出单独运营时最好的工资和附属建筑数量作为候选，
+//! 再用集束搜索每次向规划中加入一座候选建筑，保留得分最高的若干规划继续扩展。
+//! 得分暂时下降的规划也会继续扩展，以便越过需要先建上游建筑的低谷；返回搜索中见过的最好的规划。
+//! 预算或建筑数量用完、或者连续几步都没有找到更好的规划时停止。
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashSet},
+};
+
+use super::{
+    building::{info, types::Type, OutbuildingAmount, WorkerWage},
+    market::Market,
+    money::Money,
+    recipe::{self, Item},
+    research::Unlocks,
+    settings::Settings,
+    source, Condition, Options, Report, Result, Simulator,
+};
+
+/// 集束搜索每一步保留的规划数。
+const BEAM_WIDTH: usize = 3;
+/// 最多加入的建筑数，避免预算很大时搜索过久。
+pub const MAX_STEPS: u32 = 100;
+/// 连续这么多步没有找到更好的规划时停止。
+const PATIENCE: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Objective {
+    /// 月利润。
+    Profit,
+    /// 月投资回报率。回报率相同时利润高者优先。
+    Roi,
+}
+
+#[derive(Debug, Clone)]
+pub struct Constraints {
+    pub budget: Money,
+    pub objective: Objective,
+    /// 允许使用的建筑类型，`None` 表示不限。
+    pub allowed: Option<Vec<Type>>,
+    /// 建筑数量上限。
+    pub max_buildings: Option<u32>,
+    /// 是否允许从市场购买原料。不允许时，允许且已解锁的建筑能生产的原料必须由规划自己生产，
+    /// 缺少这些原料的建筑降低开工率；这些建筑都不能生产的原料仍按市场价购买。
+    pub buy_inputs: bool,
+    /// 已研究的节点，`None` 表示不限。
+    pub researched: Option<BTreeSet<String>>,
+    /// 评估规划时使用的游戏设置。
+    pub settings: Settings,
+    /// 评估规划时使用的市场设置。
+    pub market: Market,
+}
+
+impl Default for Constraints {
+    fn default() -> Self {
+        Constraints {
+            budget: Money::from(1_000_000),
+            objective: Objective::Profit,
+            allowed: None,
+            max_buildings: None,
+            buy_inputs: true,
+            researched: None,
+            settings: Settings::default(),
+            market: Market::default(),
+        }
+    }
+}
+
+/// 搜索到的一个规划。
+#[derive(Debug)]
+pub struct Solution {
+    pub conditions: Vec<Condition>,
+    pub report: Report,
+}
+
+/// 一座候选建筑。
+struct Candidate {
+    condition: Condition,
+    price: Money,
+}
+
+/// 搜索中的一个规划。
+#[derive(Clone)]
+struct State {
+    conditions: Vec<Condition>,
+    price: Money,
+    buildings: u32,
+    profit: f64,
+    score: f64,
+}
+
+impl Constraints {
+    fn options(&self) -> Options {
+        Options {
+            throttle: !self.buy_inputs,
+        }
+    }
+
+    /// 是否允许使用该建筑类型，不考虑研究。
+    fn allows(&self, building_type: Type) -> bool {
+        self.allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(&building_type))
+    }
+
+    /// 规划用到的原料中，允许且已解锁的建筑都不能生产的物品。
+    fn unproducible_inputs(&self, conditions: &[Condition]) -> BTreeSet<Item> {
+        let unlocks = Unlocks::new(self.researched.as_ref());
+        conditions
+            .iter()
+            .filter_map(|c| c.recipe_id())
+            .filter_map(|id| recipe::get(id).ok())
+            .flat_map(|recipe| recipe.inputs().map(|(item, _)| item))
+            .filter(|item| {
+                !source::producers(*item, unlocks)
+                    .iter()
+                    .any(|(building_type, _)| self.allows(*building_type))
+            })
+            .collect()
+    }
+
+    fn simulator(&self, conditions: &[Condition]) -> Result<Simulator> {
+        let simulator = Simulator::from_conditions(conditions)?
+            .with_settings(self.settings.clone())
+            .with_market(self.market.clone());
+        if self.buy_inputs {
+            return Ok(simulator);
+        }
+        Ok(simulator.with_bought_inputs(self.unproducible_inputs(conditions)))
+    }
+
+    fn evaluate(&self, conditions: &[Condition]) -> Result<Report> {
+        self.simulator(conditions)?
+            .with_options(self.options())
+            .simulate()
+    }
+
+    fn score(&self, report: &Report) -> f64 {
+        match self.objective {
+            Objective::Profit => report.monthly_profit().value() as f64,
+            Objective::Roi => report.monthly_roi().unwrap_or(0.0),
+        }
+    }
+
+    /// 每种允许且已解锁的建筑和配方各取单独运营时利润最高和回报率最高的设置，工资只在预设档位中选。
+    /// 单独运营时原料总是按市场价购买，否则需要原料的建筑无法比较。
+    fn candidates(&self) -> Vec<Candidate> {
+        let mut result: Vec<Candidate> = vec![];
+        let unlocks = Unlocks::new(self.researched.as_ref());
+        let mut types = info::infos().keys().copied().collect::<Vec<_>>();
+        types.sort();
+        for building_type in types {
+            if !self.allows(building_type) || !unlocks.building(building_type) {
+                continue;
+            }
+            let Ok(info) = info::get(building_type) else {
+                continue;
+            };
+            let outbuildings = match building_type {
+                Type::Factory(_) => vec![OutbuildingAmount::Five],
+                _ => enum_iterator::all::<OutbuildingAmount>().collect(),
+            };
+            for recipe_id in info.recipes().iter().filter(|id| unlocks.recipe(id)) {
+                let mut settings = vec![];
+                for wage in WorkerWage::PRESETS {
+                    for outbuilding in outbuildings.iter() {
+                        let condition =
+                            Condition::new(building_type, recipe_id.clone(), wage, *outbuilding, 1);
+                        let Ok(report) = self
+                            .simulator(std::slice::from_ref(&condition))
+                            .and_then(|sim| sim.simulate())
+                        else {
+                            continue;
+                        };
+                        if report.total_price() > self.budget {
+                            continue;
+                        }
+                        let profit = report.monthly_profit().value() as f64;
+                        settings.push((
+                            condition,
+                            report.total_price(),
+                            profit,
+                            report.monthly_roi().unwrap_or(0.0),
+                        ));
+                    }
+                }
+                let best_profit = settings.iter().max_by(|a, b| a.2.total_cmp(&b.2));
+                let best_roi = settings.iter().max_by(|a, b| a.3.total_cmp(&b.3));
+                for (condition, price, _, _) in best_profit.into_iter().chain(best_roi) {
+                    if !result.iter().any(|c| c.condition == *condition) {
+                        result.push(Candidate {
+                            condition: condition.clone(),
+                            price: *price,
+                        });
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+/// 向规划加入一座候选建筑，已有相同设置的条件时增加其数量。
+fn add(conditions: &[Condition], candidate: &Condition) -> Vec<Condition> {
+    let mut result = conditions.to_vec();
+    for cond in result.iter_mut() {
+        let amount = cond.amount();
+        let mut template = cond.clone();
+        template.set_amount(1);
+        if template == *candidate && amount < u8::MAX {
+            cond.set_amount(amount + 1);
+            return result;
+        }
+    }
+    result.push(candidate.clone());
+    result
+}
+
+/// 与条件顺序无关的规划标识，用于去重。
+fn key(conditions: &[Condition]) -> String {
+    let mut parts = conditions
+        .iter()
+        .map(|c| format!("{:?}", c))
+        .collect::<Vec<_>>();
+    parts.sort();
+    parts.join(";")
+}
+
+/// 得分高的在前，得分相同时利润高的在前。
+fn rank(a: &State, b: &State) -> Ordering {
+    b.score
+        .total_cmp(&a.score)
+        .then(b.profit.total_cmp(&a.profit))
+}
+
+/// 在约束内搜索，返回得分最高的至多 `results` 个规划，按得分从高到低排列。
+pub fn optimize(constraints: &Constraints, results: usize) -> Result<Vec<Solution>> {
+    let candidates = constraints.candidates();
+    let mut beam = vec![State {
+        conditions: vec![],
+        price: Money::zero(),
+        buildings: 0,
+        profit: 0.0,
+        score: 0.0,
+    }];
+    let mut best: Vec<State> = vec![];
+    let mut seen = HashSet::new();
+    let mut stalled = 0;
+    for _ in 0..MAX_STEPS {
+        let mut next: Vec<State> = vec![];
+        for state in beam.iter() {
+            if constraints
+                .max_buildings
+                .is_some_and(|max| state.buildings >= max)
+            {
+                continue;
+            }
+            for candidate in candidates.iter() {
+                let price = state.price + candidate.price;
+                if price > constraints.budget {
+                    continue;
+                }
+                let conditions = add(&state.conditions, &candidate.condition);
+                if !seen.insert(key(&conditions)) {
+                    continue;
+                }
+                let Ok(report) = constraints.evaluate(&conditions) else {
+                    continue;
+                };
+                next.push(State {
+                    conditions,
+                    price,
+                    buildings: state.buildings + 1,
+                    profit: report.monthly_profit().value() as f64,
+                    score: constraints.score(&report),
+                });
+            }
+        }
+        if next.is_empty() {
+            break;
+        }
+        next.sort_by(rank);
+        next.truncate(BEAM_WIDTH);
+        let previous = best.first().cloned();
+        best.extend(next.iter().cloned());
+        best.sort_by(rank);
+        best.truncate(results);
+        if previous.is_some_and(|previous| rank(&best[0], &previous) != Ordering::Less) {
+            stalled += 1;
+            if stalled >= PATIENCE {
+                break;
+            }
+        } else {
+            stalled = 0;
+        }
+        beam = next;
+    }
+    best.into_iter()
+        .map(|state| {
+            let report = constraints.evaluate(&state.conditions)?;
+            Ok(Solution {
+                conditions: state.conditions,
+                report,
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn optimize_test() {
+    use super::building::types;
+    let constraints = Constraints {
+        budget: Money::from(2_000_000),
+        objective: Objective::Profit,
+        allowed: Some(vec![
+            Type::Collector(types::Collector::Lumberyard),
+            Type::Factory(types::Factory::CarpentryCenter),
+        ]),
+        max_buildings: Some(6),
+        buy_inputs: false,
+        researched: None,
+        settings: Settings::default(),
+        market: Market::default(),
+    };
+    let solutions = optimize(&constraints, 3).unwrap();
+    assert!(!solutions.is_empty() && solutions.len() <= 3);
+    for solution in solutions.iter() {
+        assert!(solution.report.total_price() <= constraints.budget);
+        let buildings = solution
+            .conditions
+            .iter()
+            .map(|c| c.amount() as u32)
+            .sum::<u32>();
+        assert!(buildings <= 6);
+        assert_eq!(solution.report.monthly_material_cost(), Money::zero());
+    }
+    assert!(solutions
+        .windows(2)
+        .all(|w| w[0].report.monthly_profit() >= w[1].report.monthly_profit()));
+
+    // 最好的规划不比单独一座伐木场差
+    let lumberyard = Condition::new(
+        Type::Collector(types::Collector::Lumberyard),
+        super::recipe::Id(String::from("Wood")),
+        WorkerWage::PERCENT_100,
+        OutbuildingAmount::Five,
+        1,
+    );
+    let single = constraints.evaluate(&[lumberyard]).unwrap();
+    assert!(solutions[0].report.monthly_profit() >= single.monthly_profit());
+
+    // 不允许伐木场时木材无法自产，仍按市场价购买
+    let carpentry_only = Constraints {
+        allowed: Some(vec![Type::Factory(types::Factory::CarpentryCenter)]),
+        ..constraints
+    };
+    let solutions = optimize(&carpentry_only, 1).unwrap();
+    assert_eq!(solutions.len(), 1);
+    assert!(solutions[0].report.monthly_material_cost() > Money::zero());
+}
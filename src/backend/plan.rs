@@ -0,0 +1,212 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 规划文件的读写。
+//!
+//! 规划文件可以是 YAML 或 JSON，按扩展名区分。文件中记录了格式版本，读取旧版本时会迁移到当前版本。
+//! 版本 0 是不带元数据、只有条件列表的文件。版本 1 的工资只能是 `Percent100` 这样的预设档位，
+//! 版本 2 起工资是任意百分比；预设档位在读取时仍然接受。
+
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use std::collections::BTreeSet;
+
+use super::{logistics::Transport, market::Market, settings::Settings, Condition, Result};
+
+/// 当前规划文件格式版本。
+pub const CURRENT_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, std::io::Error),
+    Yaml(serde_yaml::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            Error::Yaml(e) => write!(f, "invalid plan: {}", e),
+            Error::Json(e) => write!(f, "invalid plan: {}", e),
+            Error::UnsupportedVersion(v) => write!(
+                f,
+                "plan version {} is newer than supported version {}",
+                v, CURRENT_VERSION
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    Json,
+}
+
+impl Format {
+    /// 按扩展名判断格式，`.json` 以外的都视为 YAML。
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Format::Json,
+            _ => Format::Yaml,
+        }
+    }
+
+    fn parse<T: serde::de::DeserializeOwned>(&self, raw: &str) -> std::result::Result<T, Error> {
+        match self {
+            Format::Yaml => serde_yaml::from_str(raw).map_err(Error::Yaml),
+            Format::Json => serde_json::from_str(raw).map_err(Error::Json),
+        }
+    }
+}
+
+/// 一个规划：条件列表和元数据。
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Plan {
+    pub version: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub notes: String,
+    pub conditions: Vec<Condition>,
+    /// 运输设置，条件中的地点名称对应这里的路线。
+    #[serde(default)]
+    pub logistics: Transport,
+    /// 已研究的节点。为 `None` 时不限制可用的配方和建筑。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub researched: Option<BTreeSet<String>>,
+    /// 游戏设置，与默认设置相同时不保存。
+    #[serde(default, skip_serializing_if = "Settings::is_default")]
+    pub settings: Settings,
+    /// 市场设置，没有城镇时不保存。
+    #[serde(default, skip_serializing_if = "Market::is_default")]
+    pub market: Market,
+}
+
+impl Default for Plan {
+    fn default() -> Self {
+        Plan {
+            version: CURRENT_VERSION,
+            name: String::new(),
+            notes: String::new(),
+            conditions: vec![],
+            logistics: Transport::default(),
+            researched: None,
+            settings: Settings::default(),
+            market: Market::default(),
+        }
+    }
+}
+
+/// 仅用于读取版本号。
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+impl Plan {
+    pub fn load(path: &Path) -> Result<Plan> {
+        let raw = std::fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+        Self::parse(&raw, Format::from_path(path))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let raw = self.to_string(Format::from_path(path))?;
+        std::fs::write(path, raw).map_err(|e| Error::Io(path.to_owned(), e))?;
+        Ok(())
+    }
+
+    /// 解析规划，并迁移到当前版本。版本 0 的规划只是条件列表，其他文档缺少版本号时报错。
+    pub fn parse(raw: &str, format: Format) -> Result<Plan> {
+        let version = match format.parse::<Header>(raw) {
+            Ok(header) => header.version,
+            Err(_) if format.parse::<Vec<serde::de::IgnoredAny>>(raw).is_ok() => 0,
+            Err(e) => return Err(e.into()),
+        };
+        let plan = match version {
+            0 => Plan {
+                conditions: format.parse(raw)?,
+                ..Default::default()
+            },
+            1 => Plan {
+                version: CURRENT_VERSION,
+                ..format.parse(raw)?
+            },
+            CURRENT_VERSION => format.parse(raw)?,
+            v => return Err(Error::UnsupportedVersion(v).into()),
+        };
+        Ok(plan)
+    }
+
+    pub fn to_string(&self, format: Format) -> Result<String> {
+        let raw = match format {
+            Format::Yaml => serde_yaml::to_string(self).map_err(Error::Yaml)?,
+            Format::Json => serde_json::to_string_pretty(self).map_err(Error::Json)?,
+        };
+        Ok(raw)
+    }
+}
+
+#[test]
+fn plan_round_trip_test() {
+    use super::{building, recipe::Id};
+    let plan = Plan {
+        name: String::from("toys"),
+        conditions: vec![Condition::Farm {
+            building_type: building::types::Farm::Orchard,
+            recipe_id: Id(String::from("Apples")),
+            worker_wage: building::WorkerWage::PERCENT_125,
+            field_amount: building::OutbuildingAmount::Three,
+            amount: 2,
+            location: None,
+        }],
+        ..Default::default()
+    };
+    for format in [Format::Yaml, Format::Json] {
+        let raw = plan.to_string(format).unwrap();
+        let parsed = Plan::parse(&raw, format).unwrap();
+        assert_eq!(parsed.name, "toys");
+        assert_eq!(
+            format!("{:?}", parsed.conditions),
+            format!("{:?}", plan.conditions)
+        );
+    }
+}
+
+#[test]
+fn plan_migration_test() {
+    let raw = "- !Factory\n  building_type: ToyFactory\n  recipe_id: Dolls\n  worker_wage: Percent100\n  amount: 1\n";
+    let plan = Plan::parse(raw, Format::Yaml).unwrap();
+    assert_eq!(plan.version, CURRENT_VERSION);
+    assert_eq!(plan.conditions.len(), 1);
+    assert!(Plan::parse("version: 99\nconditions: []\n", Format::Yaml).is_err());
+    // 不是列表又没有版本号时报告版本号的错误，而不是当作条件列表解析
+    let Err(e) = Plan::parse("name: toys\n", Format::Yaml) else {
+        panic!()
+    };
+    assert!(e.to_string().contains("version"), "{}", e);
+    assert!(Plan::parse("{\"version\": \"two\"}", Format::Json).is_err());
+
+    let raw = "version: 1\nconditions:\n- !Factory\n  building_type: ToyFactory\n  recipe_id: Dolls\n  worker_wage: Percent75\n  amount: 1\n";
+    let plan = Plan::parse(raw, Format::Yaml).unwrap();
+    assert_eq!(plan.version, CURRENT_VERSION);
+    assert_eq!(
+        plan.conditions[0].worker_wage(),
+        Some(super::building::WorkerWage::PERCENT_75)
+    );
+}
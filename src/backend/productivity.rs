@@ -12,26 +12,124 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! 生产速率。速率以每天的数量表示，内部用分数精确保存，只在换算成每月数量时才转为浮点数，
+//! 因此收支平衡的生产链在多次加减后净速率恰好为零。
+
 use std::{
     collections::{hash_map::Iter, HashMap},
     iter::Sum,
-    ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign},
+    ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign},
 };
 
-use super::{money, recipe::Item};
+use num_rational::Ratio;
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, Signed, Zero};
+
+use super::{money, recipe::Item, settings::Settings, ErrorImpl, Result};
+
+/// 默认每月的天数。游戏设置可以修改月长，见 `Settings::month_length`。
+pub const DAYS_PER_MONTH: f64 = 30.0;
+
+/// 浮点数换算成分数时使用的分母。不超过 9 位小数的倍率（工资倍率、游戏设置等）能精确表示。
+const SCALE: i128 = 1_000_000_000;
+
+type Rate = Ratio<i128>;
+
+/// NaN 和无穷大没有对应的分数，返回 `None`。超出范围的有限值取最接近的可表示值。
+fn to_rate(value: f64) -> Option<Rate> {
+    value
+        .is_finite()
+        .then(|| Rate::new((value * SCALE as f64).round() as i128, SCALE))
+}
+
+/// 调用者保证为有限值的浮点数，例如经过 `Settings::check` 的月长和倍率。
+fn finite_rate(value: f64) -> Rate {
+    to_rate(value).unwrap_or_else(|| panic!("{} is not a finite rate", value))
+}
 
-#[derive(Clone, Debug, Copy, PartialEq, PartialOrd)]
-pub struct Speed(f64);
+fn to_f64(rate: &Rate) -> f64 {
+    let (numer, denom) = (rate.numer(), rate.denom());
+    (numer / denom) as f64 + (numer % denom) as f64 / *denom as f64
+}
+
+/// 精确运算溢出时退回到按 `SCALE` 舍入的结果。只有分母极大时才会发生。
+fn checked(exact: Option<Rate>, approximate: impl FnOnce() -> f64) -> Rate {
+    exact.unwrap_or_else(|| finite_rate(approximate()))
+}
+
+/// 每天生产（为正）或消耗（为负）的数量。
+#[derive(Clone, Debug, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Speed(Rate);
 
 impl Speed {
-    pub fn monthly(&self) -> f64 {
-        self.0 * 30.0
+    pub const ZERO: Speed = Speed(Rate::new_raw(0, 1));
+
+    /// 每天 `value` 件。NaN 和无穷大返回错误。
+    pub fn from_f64(value: f64) -> Result<Speed> {
+        to_rate(value)
+            .map(Speed)
+            .ok_or_else(|| ErrorImpl::InvalidRate(format!("{} is not finite", value)).into())
+    }
+
+    /// 每 `days` 天 `amount` 件。`days` 为零时速率无穷大，返回错误。
+    pub fn per_days(amount: i64, days: u16) -> Result<Speed> {
+        if days == 0 {
+            return Err(ErrorImpl::ZeroDayToGen.into());
+        }
+        Ok(Speed(Rate::new(amount as i128, days as i128)))
+    }
+
+    /// 每月的数量，`month_length` 为每月的天数。
+    pub fn per_month(&self, month_length: f64) -> f64 {
+        to_f64(&self.monthly(month_length))
+    }
+
+    fn monthly(&self, month_length: f64) -> Rate {
+        let month_length = finite_rate(month_length);
+        checked(self.0.checked_mul(&month_length), || {
+            to_f64(&self.0) * to_f64(&month_length)
+        })
     }
-}
 
-impl From<f64> for Speed {
-    fn from(value: f64) -> Self {
-        Speed(value)
+    /// 按单价 `price` 计算每月的金额，只在最后舍入一次。
+    pub fn monthly_value(&self, price: money::Money, month_length: f64) -> money::Money {
+        let total = self.times(price).monthly(month_length);
+        money::Money::from(total.round().to_integer() as i64)
+    }
+
+    /// 乘以单价，得到每天的金额。
+    fn times(&self, price: money::Money) -> Speed {
+        let price = Rate::from_integer(price.value() as i128);
+        Speed(checked(self.0.checked_mul(&price), || {
+            to_f64(&self.0) * to_f64(&price)
+        }))
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0.is_positive()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0.is_negative()
+    }
+
+    /// 与 `other` 每天的速率相差不超过 `tolerance`。
+    pub fn approx_eq(&self, other: Speed, tolerance: f64) -> bool {
+        to_f64(&(*self - other).0.abs()) <= tolerance
+    }
+
+    /// 除以 `divisor`。除数为零或不是有限数时返回错误。
+    /// 除数小到按 `SCALE` 换算后为零时，按浮点数相除再换算回分数。
+    pub fn checked_div(&self, divisor: f64) -> Result<Speed> {
+        if divisor == 0.0 || !divisor.is_finite() {
+            return Err(ErrorImpl::InvalidRate(format!("cannot divide by {}", divisor)).into());
+        }
+        let rate = finite_rate(divisor);
+        if rate.is_zero() {
+            return Speed::from_f64(to_f64(&self.0) / divisor);
+        }
+        Ok(Speed(checked(self.0.checked_div(&rate), || {
+            to_f64(&self.0) / divisor
+        })))
     }
 }
 
@@ -39,7 +137,9 @@ impl Add for Speed {
     type Output = Speed;
 
     fn add(self, rhs: Self) -> Self::Output {
-        Speed(self.0 + rhs.0)
+        Speed(checked(self.0.checked_add(&rhs.0), || {
+            to_f64(&self.0) + to_f64(&rhs.0)
+        }))
     }
 }
 
@@ -47,37 +147,70 @@ impl Sub for Speed {
     type Output = Speed;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        Speed(self.0 - rhs.0)
+        Speed(checked(self.0.checked_sub(&rhs.0), || {
+            to_f64(&self.0) - to_f64(&rhs.0)
+        }))
     }
 }
 
 impl Mul<f64> for Speed {
     type Output = Speed;
 
+    /// 倍率先按 `SCALE` 换算成分数。倍率不是有限数时 panic。
     fn mul(self, rhs: f64) -> Self::Output {
-        Speed(self.0 * rhs)
+        if rhs == 1.0 {
+            return self;
+        }
+        let factor = finite_rate(rhs);
+        Speed(checked(self.0.checked_mul(&factor), || {
+            to_f64(&self.0) * rhs
+        }))
+    }
+}
+
+impl Div<f64> for Speed {
+    type Output = Speed;
+
+    /// 见 `Speed::checked_div`。除数为零或不是有限数时 panic。
+    fn div(self, rhs: f64) -> Self::Output {
+        self.checked_div(rhs).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+impl Neg for Speed {
+    type Output = Speed;
+
+    fn neg(self) -> Self::Output {
+        Speed(-self.0)
     }
 }
 
 impl AddAssign for Speed {
     fn add_assign(&mut self, rhs: Self) {
-        self.0 += rhs.0
+        *self = *self + rhs
     }
 }
 
 impl SubAssign for Speed {
     fn sub_assign(&mut self, rhs: Self) {
-        self.0 -= rhs.0
+        *self = *self - rhs
     }
 }
 
 impl MulAssign<f64> for Speed {
     fn mul_assign(&mut self, rhs: f64) {
-        self.0 *= rhs
+        *self = *self * rhs
     }
 }
 
-#[derive(Debug)]
+impl DivAssign<f64> for Speed {
+    fn div_assign(&mut self, rhs: f64) {
+        *self = *self / rhs
+    }
+}
+
+/// 各物品的生产速率。没有列出的物品速率为零，比较时速率为零的项与不存在的项相同。
+#[derive(Debug, Clone, Default)]
 pub struct Productivity {
     inner: HashMap<Item, Speed>,
 }
@@ -87,26 +220,80 @@ impl Productivity {
         Productivity { inner }
     }
 
-    pub fn iter(&self) -> Iter<Item, Speed> {
+    pub fn iter(&self) -> Iter<'_, Item, Speed> {
         self.inner.iter()
     }
 
-    pub fn estimated_monthly_sales(&self) -> money::Money {
-        let val = self
+    /// 拆分为产出和消耗两部分，两者的速率都为正。
+    pub fn split(&self) -> (Productivity, Productivity) {
+        let outputs = self
+            .inner
+            .iter()
+            .filter(|(_, speed)| speed.is_positive())
+            .map(|(item, speed)| (*item, *speed))
+            .collect();
+        let inputs = self
             .inner
             .iter()
-            .filter(|(_, speed)| speed.0 > 0.0)
-            .map(|(item, speed)| item.price().value() as f64 * speed.monthly())
-            .sum();
-        money::Money::from(f64::round(val) as i64)
+            .filter(|(_, speed)| speed.is_negative())
+            .map(|(item, speed)| (*item, -*speed))
+            .collect();
+        (Productivity::new(outputs), Productivity::new(inputs))
     }
-    pub fn estimated_monthly_material_cost(&self) -> money::Money {
-        let val = self
+
+    /// 某物品的生产速率，不存在时为 `None`。
+    pub fn get(&self, item: Item) -> Option<Speed> {
+        self.inner.get(&item).copied()
+    }
+
+    /// 设置某物品的生产速率，返回原来的速率。
+    pub fn set(&mut self, item: Item, speed: Speed) -> Option<Speed> {
+        self.inner.insert(item, speed)
+    }
+
+    /// 删除每天速率的绝对值不超过 `tolerance` 的物品。`tolerance` 为 0 时只删除速率恰好为零的物品。
+    pub fn prune(&mut self, tolerance: f64) {
+        self.inner
+            .retain(|_, speed| !speed.approx_eq(Speed::ZERO, tolerance));
+    }
+
+    /// 各物品每天的速率都相差不超过 `tolerance`，不存在的物品按零计。
+    pub fn approx_eq(&self, other: &Productivity, tolerance: f64) -> bool {
+        let get = |p: &Productivity, item: &Item| p.inner.get(item).copied().unwrap_or(Speed::ZERO);
+        self.inner
+            .keys()
+            .chain(other.inner.keys())
+            .all(|item| get(self, item).approx_eq(get(other, item), tolerance))
+    }
+
+    /// 将所有生产速率除以 `divisor`。除数不是有限数或为零时返回错误。
+    pub fn checked_div(&self, divisor: f64) -> Result<Productivity> {
+        let inner = self
+            .inner
             .iter()
-            .filter(|(_, speed)| speed.0 < 0.0)
-            .map(|(item, speed)| item.price().value() as f64 * -speed.monthly())
-            .sum();
-        money::Money::from(f64::round(val) as i64)
+            .map(|(item, speed)| Ok((*item, speed.checked_div(divisor)?)))
+            .collect::<Result<_>>()?;
+        Ok(Productivity { inner })
+    }
+
+    /// 按市场价出售所有净产出的每月收入。各物品精确相加，只在最后舍入一次。
+    pub fn estimated_monthly_sales(&self, settings: &Settings) -> Result<money::Money> {
+        let (outputs, _) = self.split();
+        outputs.monthly_value(settings)
+    }
+
+    /// 按市场价购买所有净消耗的每月费用。各物品精确相加，只在最后舍入一次。
+    pub fn estimated_monthly_material_cost(&self, settings: &Settings) -> Result<money::Money> {
+        let (_, inputs) = self.split();
+        inputs.monthly_value(settings)
+    }
+
+    fn monthly_value(&self, settings: &Settings) -> Result<money::Money> {
+        let mut total = Speed(Rate::zero());
+        for (item, speed) in self.iter() {
+            total += speed.times(settings.price(*item)?);
+        }
+        Ok(total.monthly_value(money::Money::from(1), settings.month_length))
     }
 }
 
@@ -121,7 +308,7 @@ impl Sum for Productivity {
 impl Add for Productivity {
     type Output = Productivity;
 
-    /// 将两个产能相加。对于两个 `Productivity` 共有的物品，速率相加；不共有的物品，插入新键值对。注意：如果同一物品产能相加结果为零，仍然不会删除这个物品！
+    /// 将两个产能相加。对于两个 `Productivity` 共有的物品，速率相加；不共有的物品，插入新键值对。注意：如果同一物品产能相加结果为零，仍然不会删除这个物品！需要时用 `prune` 删除。
     fn add(self, rhs: Self) -> Self::Output {
         let mut result = self;
         rhs.inner.into_iter().for_each(|(item, productivity)| {
@@ -148,20 +335,42 @@ impl AddAssign for Productivity {
 
 impl Sub for Productivity {
     type Output = Productivity;
-    /// 在结果上等于 `a + (b * -1)`，但比它更快。
+    /// 在结果上等于 `a + (-b)`，但比它更快。只在 `rhs` 中出现的物品取相反数插入。
     fn sub(self, rhs: Self) -> Self::Output {
         let mut result = self;
+        result -= rhs;
+        result
+    }
+}
+
+impl SubAssign for Productivity {
+    fn sub_assign(&mut self, rhs: Self) {
         rhs.inner.into_iter().for_each(|(item, productivity)| {
-            result
-                .inner
+            self.inner
                 .entry(item)
                 .and_modify(|prod| *prod -= productivity)
-                .or_insert(productivity);
+                .or_insert(-productivity);
         });
+    }
+}
+
+impl Neg for Productivity {
+    type Output = Productivity;
+
+    fn neg(self) -> Self::Output {
+        let mut result = self;
+        result.inner.values_mut().for_each(|x| *x = -*x);
         result
     }
 }
 
+impl PartialEq for Productivity {
+    /// 精确比较，速率为零的项与不存在的项相同。
+    fn eq(&self, other: &Self) -> bool {
+        self.approx_eq(other, 0.0)
+    }
+}
+
 impl Mul<f64> for Productivity {
     type Output = Productivity;
 
@@ -172,3 +381,202 @@ impl Mul<f64> for Productivity {
         result
     }
 }
+
+impl Div<f64> for Productivity {
+    type Output = Productivity;
+
+    /// 将所有生产速率除以 `rhs`。见 `Productivity::checked_div`，除数为零或不是有限数时 panic。
+    fn div(self, rhs: f64) -> Self::Output {
+        self.checked_div(rhs).unwrap_or_else(|e| panic!("{}", e))
+    }
+}
+
+#[test]
+fn speed_test() {
+    // 1/35 加 350 次恰好为 10，浮点数做不到
+    let step = Speed::per_days(1, 35).unwrap();
+    let total = (0..350).fold(Speed::ZERO, |sum, _| sum + step);
+    assert_eq!(total, Speed::from_f64(10.0).unwrap());
+    assert_eq!(total.per_month(DAYS_PER_MONTH), 300.0);
+    assert!(Speed::per_days(1, 0).is_err());
+
+    // 非有限值和零除数返回错误，极小的除数仍能相除
+    assert!(Speed::from_f64(f64::NAN).is_err());
+    assert!(Speed::from_f64(f64::INFINITY).is_err());
+    assert!(step.checked_div(0.0).is_err());
+    assert!(step.checked_div(f64::NAN).is_err());
+    let tiny = step.checked_div(1e-12).unwrap();
+    assert!((tiny.per_month(1.0) - 1e12 / 35.0).abs() < 1.0);
+
+    // 收支平衡的生产链净速率恰好为零
+    let made = Productivity::new(HashMap::from([(
+        Item::Wood,
+        Speed::per_days(3, 35).unwrap(),
+    )]));
+    let used = Productivity::new(HashMap::from([(
+        Item::Wood,
+        Speed::per_days(-1, 35).unwrap(),
+    )]));
+    let net = made + used.clone() + used.clone() + used;
+    assert_eq!(net.get(Item::Wood).unwrap().per_month(DAYS_PER_MONTH), 0.0);
+    assert!(!net.get(Item::Wood).unwrap().is_positive());
+
+    // 金额精确计算后只舍入一次
+    let speed = Speed::per_days(1, 3).unwrap();
+    assert_eq!(
+        speed.monthly_value(money::Money::from(10), DAYS_PER_MONTH),
+        money::Money::from(100)
+    );
+    let settings = Settings::default();
+    let price = Item::Wood.price().unwrap();
+    let sales = Productivity::new(HashMap::from([(Item::Wood, speed)]));
+    assert_eq!(
+        sales.estimated_monthly_sales(&settings).unwrap(),
+        price * 10
+    );
+    assert_eq!(
+        (sales * -1.0)
+            .estimated_monthly_material_cost(&settings)
+            .unwrap(),
+        price * 10
+    );
+
+    // 分母过大时退回到近似值而不会溢出
+    let mut speed = Speed::per_days(1, 7).unwrap();
+    for factor in [
+        0.123456789,
+        1.000000007,
+        3.141592651,
+        0.999999937,
+        2.718281821,
+    ] {
+        for _ in 0..4 {
+            speed *= factor;
+            speed += Speed::per_days(1, 13).unwrap();
+        }
+    }
+    assert!(speed.per_month(DAYS_PER_MONTH).is_finite());
+}
+
+#[test]
+fn productivity_sub_test() {
+    // 只在右边出现的物品取相反数
+    let wood = Productivity::new(HashMap::from([(
+        Item::Wood,
+        Speed::per_days(2, 5).unwrap(),
+    )]));
+    let water = Productivity::new(HashMap::from([(
+        Item::Water,
+        Speed::per_days(1, 3).unwrap(),
+    )]));
+    let diff = wood.clone() - water.clone();
+    assert_eq!(diff.get(Item::Water), Some(Speed::per_days(-1, 3).unwrap()));
+    assert_eq!(diff, wood.clone() + -water.clone());
+    assert_eq!(diff, wood.clone() + water.clone() * -1.0);
+
+    let mut zero = wood.clone() - wood.clone();
+    assert_eq!(zero, Productivity::default());
+    assert!(zero.get(Item::Wood).is_some());
+    zero.prune(0.0);
+    assert!(zero.get(Item::Wood).is_none());
+
+    let mut almost = wood.clone();
+    almost.set(
+        Item::Wood,
+        Speed::per_days(2, 5).unwrap() + Speed::from_f64(1e-7).unwrap(),
+    );
+    assert_ne!(almost, wood);
+    assert!(almost.approx_eq(&wood, 1e-6));
+    almost -= wood;
+    almost.prune(1e-6);
+    assert_eq!(almost.iter().count(), 0);
+}
+
+#[cfg(test)]
+fn arb_productivity() -> impl proptest::strategy::Strategy<Value = Productivity> {
+    use proptest::prelude::*;
+    let items = enum_iterator::all::<Item>().take(8).collect::<Vec<_>>();
+    proptest::collection::hash_map(
+        proptest::sample::select(items),
+        (-200i64..200, 1u16..60).prop_map(|(amount, days)| Speed::per_days(amount, days).unwrap()),
+        0..6,
+    )
+    .prop_map(Productivity::new)
+}
+
+/// 两位小数的倍率能精确换算成分数，运算律应当精确成立。
+#[cfg(test)]
+fn arb_scalar() -> impl proptest::strategy::Strategy<Value = f64> {
+    use proptest::prelude::*;
+    (-1000i32..1000).prop_map(|x| x as f64 / 100.0)
+}
+
+#[cfg(test)]
+use proptest::{prop_assert, prop_assert_eq};
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn productivity_group_laws(
+        a in arb_productivity(),
+        b in arb_productivity(),
+        c in arb_productivity(),
+    ) {
+        let zero = Productivity::default();
+        prop_assert_eq!(
+            (a.clone() + b.clone()) + c.clone(),
+            a.clone() + (b.clone() + c.clone())
+        );
+        prop_assert_eq!(a.clone() + b.clone(), b.clone() + a.clone());
+        prop_assert_eq!(a.clone() + zero.clone(), a.clone());
+        prop_assert_eq!(a.clone() - a.clone(), zero.clone());
+        prop_assert_eq!(a.clone() + -a.clone(), zero);
+        prop_assert_eq!(a.clone() - b.clone(), a.clone() + -b.clone());
+        prop_assert_eq!(-(-a.clone()), a.clone());
+        prop_assert_eq!((a.clone() - b.clone()) + b.clone(), a);
+    }
+
+    #[test]
+    fn productivity_scalar_laws(
+        a in arb_productivity(),
+        b in arb_productivity(),
+        k in arb_scalar(),
+        l in arb_scalar(),
+    ) {
+        prop_assert_eq!((a.clone() + b.clone()) * k, a.clone() * k + b.clone() * k);
+        prop_assert_eq!(a.clone() * (k + l), a.clone() * k + a.clone() * l);
+        prop_assert_eq!((a.clone() * k) * l, a.clone() * (k * l));
+        prop_assert_eq!(a.clone() * 1.0, a.clone());
+        prop_assert_eq!(a.clone() * 0.0, Productivity::default());
+        prop_assert_eq!(a.clone() * -1.0, -a.clone());
+        if k != 0.0 {
+            prop_assert_eq!((a.clone() * k) / k, a);
+        }
+    }
+
+    #[test]
+    fn productivity_prune_and_compare(
+        a in arb_productivity(),
+        b in arb_productivity(),
+        tolerance in 0.0..1.0f64,
+    ) {
+        let zero = Speed::ZERO;
+        let mut pruned = a.clone() - b.clone() + b.clone();
+        pruned.prune(0.0);
+        prop_assert_eq!(&pruned, &a);
+        prop_assert!(pruned.iter().all(|(_, speed)| !speed.approx_eq(zero, 0.0)));
+        prop_assert!(a.approx_eq(&a, 0.0));
+        prop_assert_eq!(a.approx_eq(&b, tolerance), b.approx_eq(&a, tolerance));
+
+        let mut loose = a.clone();
+        loose.prune(tolerance);
+        prop_assert!(loose.approx_eq(&a, tolerance));
+        prop_assert!(loose.iter().all(|(_, speed)| !speed.approx_eq(zero, tolerance)));
+
+        let mut set = a.clone();
+        for (item, speed) in b.iter() {
+            set.set(*item, *speed);
+            prop_assert_eq!(set.get(*item), Some(*speed));
+        }
+    }
+}
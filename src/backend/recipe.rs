@@ -11,48 +11,29 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use std::collections::HashMap;
-
-use once_cell::sync::Lazy;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 
 use enum_iterator_derive::Sequence;
 
 use super::{
+    data,
     money::Money,
     productivity::{Productivity, Speed},
+    ErrorImpl, Result,
 };
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Id(pub String);
 
-impl ToString for Id {
-    fn to_string(&self) -> String {
-        self.0.clone()
+impl std::fmt::Display for Id {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(&self.0)
     }
 }
 
-pub static RECIPES: Lazy<HashMap<Id, Recipe>> = Lazy::new(|| {
-    lazy_static_include_str! {
-        RECIPE_RAW => "data/recipes.yaml",
-    }
-    match serde_yaml::from_str(&RECIPE_RAW) {
-        Ok(x) => x,
-        Err(e) => unreachable!("{}", e),
-    }
-});
-
-static PRICES: Lazy<HashMap<Item, Money>> = Lazy::new(|| {
-    lazy_static_include_str! {
-        PRICES_RAW => "data/global_market_prices.yaml",
-    }
-    match serde_yaml::from_str(&PRICES_RAW) {
-        Ok(x) => x,
-        Err(e) => unreachable!("{}", e),
-    }
-});
-
-#[derive(PartialEq, Debug, Eq, PartialOrd, Ord, Hash, Clone, Copy, Deserialize, Sequence)]
+#[derive(
+    PartialEq, Debug, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize, Sequence,
+)]
 pub enum Item {
     Water,              // 水
     Sand,               // 沙子
@@ -208,25 +189,25 @@ pub enum Item {
 }
 
 impl Item {
-    pub fn price(&self) -> Money {
-        match PRICES.get(&self) {
-            Some(x) => *x,
-            None => unreachable!("Item {:?} has no price!", self),
+    pub fn price(&self) -> Result<Money> {
+        match data::current().prices.get(self) {
+            Some(x) => Ok(*x),
+            None => Err(ErrorImpl::MissingPrice(*self).into()),
         }
     }
 }
 
-pub fn get(id: &Id) -> &'static Recipe {
-    match RECIPES.get(id) {
-        Some(x) => x,
-        None => unreachable!("got id=\"{}\", but it's not exists.", id.to_string()),
+pub fn get(id: &Id) -> Result<&'static Recipe> {
+    match data::current().recipes.get(id) {
+        Some(x) => Ok(x),
+        None => Err(ErrorImpl::UnknownRecipe(id.clone()).into()),
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
 pub struct Slot(Item, u64);
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize)]
 pub struct Recipe {
     inputs: [Option<Slot>; 3],
     outputs: [Option<Slot>; 3],
@@ -234,44 +215,63 @@ pub struct Recipe {
 }
 
 impl Recipe {
-    pub fn productivity(&self) -> Productivity {
+    pub fn day_to_gen(&self) -> u16 {
+        self.day_to_gen
+    }
+
+    /// 配方的输入物品及数量。
+    pub fn inputs(&self) -> impl Iterator<Item = (Item, u64)> + '_ {
+        self.inputs
+            .iter()
+            .flatten()
+            .map(|&Slot(item, amount)| (item, amount))
+    }
+
+    /// 配方的输出物品及数量。
+    pub fn outputs(&self) -> impl Iterator<Item = (Item, u64)> + '_ {
+        self.outputs
+            .iter()
+            .flatten()
+            .map(|&Slot(item, amount)| (item, amount))
+    }
+
+    /// 配方每天的产能。`day_to_gen` 为零时返回错误。
+    pub fn productivity(&self) -> Result<Productivity> {
         let inputs_productivity = Productivity::new(
-            self.inputs
-                .iter()
-                .filter_map(|&slot| slot)
-                .map(|Slot(item, amount)| {
-                    (
-                        item,
-                        Speed::from(amount as f64) * (-1.0 / self.day_to_gen as f64),
-                    )
+            self.inputs()
+                .map(|(item, amount)| {
+                    Ok((item, Speed::per_days(-(amount as i64), self.day_to_gen)?))
                 })
-                .collect(),
+                .collect::<Result<_>>()?,
         );
         let outputs_productivity = Productivity::new(
-            self.outputs
-                .iter()
-                .filter_map(|&slot| slot)
-                .map(|Slot(item, amount)| {
-                    (
-                        item,
-                        Speed::from(amount as f64) * (1.0 / self.day_to_gen as f64),
-                    )
-                })
-                .collect(),
+            self.outputs()
+                .map(|(item, amount)| Ok((item, Speed::per_days(amount as i64, self.day_to_gen)?)))
+                .collect::<Result<_>>()?,
         );
-        outputs_productivity + inputs_productivity
+        Ok(outputs_productivity + inputs_productivity)
     }
 }
 
 #[test]
 fn recipes_test() {
-    println!("{:?}", RECIPES);
+    println!("{:?}", data::current().recipes);
+    assert!(get(&Id(String::from("Wood"))).is_ok());
+    assert!(get(&Id(String::from("NoSuchRecipe"))).is_err());
 }
 
 #[test]
 fn price_test() {
     for i in enum_iterator::all::<Item>() {
-        let price = i.price();
+        let price = i.price().unwrap();
         println!("{:?}", price);
     }
 }
+
+#[test]
+fn zero_day_to_gen_test() {
+    let recipe: Recipe =
+        serde_yaml::from_str("inputs: [~, ~, ~]\noutputs: [!Slot [Wood, 1], ~, ~]\nday_to_gen: 0")
+            .unwrap();
+    assert!(recipe.productivity().is_err());
+}
@@ -0,0 +1,215 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 研究树：配方和建筑需要先研究对应的节点才能使用。
+//!
+//! 研究树来自数据文件 `research.yaml`，没有出现在任何节点中的配方和建筑一开始就可以使用。
+//! 规划中记录已研究的节点；没有记录时不做限制。
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_derive::Deserialize;
+
+use super::{
+    building::types::Type,
+    data,
+    recipe::{Id, Item},
+    source, Condition,
+};
+
+/// 一个研究节点。
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Node {
+    /// 研究点数。
+    pub cost: u32,
+    /// 前置节点。
+    #[serde(default)]
+    pub requires: Vec<String>,
+    #[serde(default)]
+    pub recipes: Vec<Id>,
+    #[serde(default)]
+    pub buildings: Vec<Type>,
+}
+
+pub fn tree() -> &'static BTreeMap<String, Node> {
+    &data::current().research
+}
+
+/// 解锁该配方的节点。
+pub fn recipe_node(id: &Id) -> Option<&'static str> {
+    tree()
+        .iter()
+        .find(|(_, node)| node.recipes.contains(id))
+        .map(|(name, _)| name.as_str())
+}
+
+/// 解锁该建筑的节点。
+pub fn building_node(building_type: Type) -> Option<&'static str> {
+    tree()
+        .iter()
+        .find(|(_, node)| node.buildings.contains(&building_type))
+        .map(|(name, _)| name.as_str())
+}
+
+/// 按已研究的节点判断配方和建筑是否可用。默认不做限制。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Unlocks<'a> {
+    researched: Option<&'a BTreeSet<String>>,
+}
+
+impl<'a> Unlocks<'a> {
+    /// `researched` 为 `None` 时所有配方和建筑都可用。
+    pub fn new(researched: Option<&'a BTreeSet<String>>) -> Unlocks<'a> {
+        Unlocks { researched }
+    }
+
+    fn node(&self, node: Option<&str>) -> bool {
+        match (self.researched, node) {
+            (Some(researched), Some(node)) => researched.contains(node),
+            _ => true,
+        }
+    }
+
+    pub fn recipe(&self, id: &Id) -> bool {
+        self.node(recipe_node(id))
+    }
+
+    pub fn building(&self, building_type: Type) -> bool {
+        self.node(building_node(building_type))
+    }
+
+    fn missing_nodes<'b>(&self, nodes: impl IntoIterator<Item = &'b str>) -> Vec<String> {
+        self.researched
+            .map_or_else(Vec::new, |researched| needed(nodes, researched))
+    }
+
+    /// 建筑还需要研究的节点，前置节点在前。
+    pub fn missing_building(&self, building_type: Type) -> Vec<String> {
+        self.missing_nodes(building_node(building_type))
+    }
+
+    /// 条件还需要研究的节点，前置节点在前。
+    pub fn missing(&self, condition: &Condition) -> Vec<String> {
+        self.missing_nodes(
+            building_node(condition.building_type())
+                .into_iter()
+                .chain(condition.recipe_id().and_then(recipe_node)),
+        )
+    }
+}
+
+/// 研究 `nodes` 还需要研究的全部节点（包括前置节点），前置节点在前。
+pub fn needed<'a>(
+    nodes: impl IntoIterator<Item = &'a str>,
+    researched: &BTreeSet<String>,
+) -> Vec<String> {
+    fn visit(name: &str, researched: &BTreeSet<String>, result: &mut Vec<String>) {
+        if researched.contains(name) || result.iter().any(|x| x == name) {
+            return;
+        }
+        if let Some(node) = tree().get(name) {
+            for prerequisite in node.requires.iter() {
+                visit(prerequisite, researched, result);
+            }
+        }
+        // 前置节点有环时可能已经加入
+        if !result.iter().any(|x| x == name) {
+            result.push(name.to_owned());
+        }
+    }
+    let mut result = vec![];
+    for name in nodes {
+        visit(name, researched, &mut result);
+    }
+    result
+}
+
+/// 节点的研究点数之和。
+pub fn cost(nodes: &[String]) -> u64 {
+    nodes
+        .iter()
+        .filter_map(|name| tree().get(name))
+        .map(|node| node.cost as u64)
+        .sum()
+}
+
+/// 一种生产某物品的方式及其所需的研究。
+#[derive(Debug, Clone)]
+pub struct Requirement {
+    pub building_type: Type,
+    pub recipe_id: Id,
+    /// 还需要研究的节点，前置节点在前；为空表示已经可以生产。
+    pub nodes: Vec<String>,
+    pub cost: u64,
+}
+
+/// 列出生产 `item` 的每种方式在已研究 `researched` 的基础上还需要的研究，按研究点数从少到多排列。
+pub fn requirements(item: Item, researched: &BTreeSet<String>) -> Vec<Requirement> {
+    let mut result = source::producers(item, Unlocks::default())
+        .into_iter()
+        .map(|(building_type, recipe_id)| {
+            let nodes = needed(
+                building_node(building_type)
+                    .into_iter()
+                    .chain(recipe_node(&recipe_id)),
+                researched,
+            );
+            let cost = cost(&nodes);
+            Requirement {
+                building_type,
+                recipe_id,
+                nodes,
+                cost,
+            }
+        })
+        .collect::<Vec<_>>();
+    result.sort_by_key(|r| r.cost);
+    result
+}
+
+#[test]
+fn research_test() {
+    use super::building::types;
+    let nothing = BTreeSet::new();
+    let locked = Unlocks::new(Some(&nothing));
+    let steel = Id(String::from("Steel"));
+    let smelter = Type::Factory(types::Factory::GlassworksAndSmelter);
+    assert!(Unlocks::default().recipe(&steel));
+    assert!(!locked.recipe(&steel));
+    assert!(!locked.building(smelter));
+    assert!(locked.recipe(&Id(String::from("Wood"))));
+    assert!(locked.building(Type::Collector(types::Collector::Lumberyard)));
+
+    let smelting = BTreeSet::from([String::from("Smelting")]);
+    assert!(Unlocks::new(Some(&smelting)).recipe(&steel));
+
+    // 前置节点在前
+    assert_eq!(
+        needed(["Automotive"], &nothing),
+        vec!["Smelting", "Petrochemicals", "Automotive"]
+    );
+    assert_eq!(
+        needed(["Automotive"], &smelting),
+        vec!["Petrochemicals", "Automotive"]
+    );
+
+    let steel = requirements(Item::Steel, &nothing);
+    assert!(!steel.is_empty());
+    assert!(steel
+        .iter()
+        .all(|r| r.nodes.contains(&String::from("Smelting"))));
+    assert!(requirements(Item::Wood, &nothing)
+        .iter()
+        .any(|r| r.nodes.is_empty()));
+}
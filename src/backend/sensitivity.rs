@@ -0,0 +1,158 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 价格敏感性分析：分别改变每种物品的市场价，看规划的利润随之变化多少。
+//!
+//! 产能不随价格变化，所以销售额和原料费都是价格的线性函数，无需重新模拟。
+
+use super::{money::Money, recipe::Item, ErrorImpl, Report, Result};
+
+/// 默认的价格变化幅度（±20%）。
+pub const DEFAULT_RANGE: f64 = 0.2;
+/// 默认在幅度内每侧取的点数。
+pub const DEFAULT_STEPS: u32 = 2;
+
+/// 某价格变化下的结果。
+#[derive(Debug, Clone, Copy)]
+pub struct Point {
+    /// 价格变化比例，例如 `-0.1` 表示降价 10%。
+    pub change: f64,
+    pub monthly_profit: Money,
+    pub profit_rate: f64,
+}
+
+/// 某物品的价格敏感性。
+#[derive(Debug, Clone)]
+pub struct Sensitivity {
+    pub item: Item,
+    pub price: Money,
+    /// 每月净产量，正值出售，负值购买。
+    pub net_monthly: f64,
+    /// 按价格变化从小到大排列。
+    pub points: Vec<Point>,
+}
+
+impl Sensitivity {
+    /// 价格变化范围内利润的最小值和最大值。
+    pub fn profit_range(&self) -> (Money, Money) {
+        let profits = self.points.iter().map(|p| p.monthly_profit);
+        (
+            profits.clone().min().unwrap_or_default(),
+            profits.max().unwrap_or_default(),
+        )
+    }
+
+    /// 价格变化范围内利润的最大差值，用于排序。
+    pub fn swing(&self) -> Money {
+        let (low, high) = self.profit_range();
+        high - low
+    }
+}
+
+/// 在 `[-range, range]` 内对称地取 `steps * 2` 个价格变化比例，不含零。
+/// `range` 不是有限的正数时返回错误。
+pub fn changes(range: f64, steps: u32) -> Result<Vec<f64>> {
+    if !range.is_finite() || range <= 0.0 {
+        return Err(ErrorImpl::InvalidRange(range).into());
+    }
+    let steps = steps.max(1);
+    let mut result = (1..=steps)
+        .rev()
+        .map(|i| -range * i as f64 / steps as f64)
+        .collect::<Vec<_>>();
+    result.extend((1..=steps).map(|i| range * i as f64 / steps as f64));
+    Ok(result)
+}
+
+/// 分析报告对 `items` 中各物品价格的敏感性；`items` 为 `None` 时分析报告中所有物品。
+/// 结果按利润变化幅度从大到小排列。
+pub fn sensitivity(
+    report: &Report,
+    items: Option<&[Item]>,
+    changes: &[f64],
+) -> Result<Vec<Sensitivity>> {
+    let sales = report.monthly_sales().value() as f64;
+    let profit = report.monthly_profit().value() as f64;
+    // 完全自给的物品净流量为零，价格变化不影响利润
+    let mut net_productivity = report.productivity().clone();
+    net_productivity.prune(0.0);
+    let mut result = vec![];
+    for (item, speed) in net_productivity.iter() {
+        if items.is_some_and(|items| !items.contains(item)) {
+            continue;
+        }
+        let net = speed.per_month(report.month_length());
+        let price = report.settings().price(*item)?;
+        // 剩余物品的收入与价格成正比，即使售价随供给下降
+        let value = report
+            .sale(*item)
+            .map_or(price.value() as f64 * net, |sale| sale.revenue);
+        let mut points = changes
+            .iter()
+            .map(|change| {
+                let profit = profit + value * change;
+                let sales = sales + value.max(0.0) * change;
+                Point {
+                    change: *change,
+                    monthly_profit: Money::from(profit.round() as i64),
+                    profit_rate: profit / sales,
+                }
+            })
+            .collect::<Vec<_>>();
+        points.sort_by(|a, b| a.change.total_cmp(&b.change));
+        result.push(Sensitivity {
+            item: *item,
+            price,
+            net_monthly: net,
+            points,
+        });
+    }
+    result.sort_by(|a, b| b.swing().cmp(&a.swing()).then(a.item.cmp(&b.item)));
+    Ok(result)
+}
+
+#[test]
+fn sensitivity_test() {
+    use super::{building, recipe::Id, Condition, Simulator};
+    let conditions = [Condition::Factory {
+        building_type: building::types::Factory::CarpentryCenter,
+        recipe_id: Id(String::from("WoodenPlanks")),
+        worker_wage: building::WorkerWage::PERCENT_100,
+        amount: 1,
+        location: None,
+    }];
+    let report = Simulator::from_conditions(&conditions)
+        .unwrap()
+        .simulate()
+        .unwrap();
+    assert_eq!(changes(0.2, 2).unwrap(), vec![-0.2, -0.1, 0.1, 0.2]);
+    assert!(changes(f64::NAN, 2).is_err());
+    assert!(changes(-0.2, 2).is_err());
+    assert!(changes(0.0, 2).is_err());
+    let result = sensitivity(&report, None, &changes(0.2, 2).unwrap()).unwrap();
+    let planks = result
+        .iter()
+        .find(|s| s.item == Item::WoodenPlanks)
+        .unwrap();
+    let wood = result.iter().find(|s| s.item == Item::Wood).unwrap();
+    // 产品涨价利润增加，原料涨价利润减少
+    assert!(planks.points[0].monthly_profit < planks.points[3].monthly_profit);
+    assert!(wood.points[0].monthly_profit > wood.points[3].monthly_profit);
+    let expected = report.monthly_sales().value() as f64 * 0.4;
+    assert!((planks.swing().value() as f64 - expected).abs() <= 1.0);
+    assert!(result.windows(2).all(|w| w[0].swing() >= w[1].swing()));
+
+    let only_wood = sensitivity(&report, Some(&[Item::Wood]), &[0.1]).unwrap();
+    assert_eq!(only_wood.len(), 1);
+}
@@ -0,0 +1,246 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 游戏设置：难度和世界设置对建造费用、维护费、市场价格、生产速度和月长的影响。
+//!
+//! 每项倍率由全局倍率和按建筑类别（市场价格按物品）的倍率相乘得到。设置随规划保存，由 `Simulator` 应用。
+
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+    building::types::Type, money::Money, productivity::DAYS_PER_MONTH, recipe::Item, ErrorImpl,
+    Result,
+};
+
+/// 建筑类别。
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    enum_iterator_derive::Sequence,
+)]
+pub enum Category {
+    Collector,
+    Farm,
+    Factory,
+    Depot,
+}
+
+impl From<Type> for Category {
+    fn from(value: Type) -> Self {
+        match value {
+            Type::Collector(_) => Category::Collector,
+            Type::Farm(_) => Category::Farm,
+            Type::Factory(_) => Category::Factory,
+            Type::Depot(_) => Category::Depot,
+        }
+    }
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn is_one(x: &f64) -> bool {
+    *x == 1.0
+}
+
+/// 按建筑类别的倍率。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Multiplier {
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    pub global: f64,
+    /// 各类别在全局倍率之上的倍率，未列出的类别为 1。
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub categories: BTreeMap<Category, f64>,
+}
+
+impl Default for Multiplier {
+    fn default() -> Self {
+        Multiplier {
+            global: 1.0,
+            categories: BTreeMap::new(),
+        }
+    }
+}
+
+impl Multiplier {
+    pub fn get(&self, category: Category) -> f64 {
+        self.global * self.categories.get(&category).copied().unwrap_or(1.0)
+    }
+
+    fn factors(&self) -> impl Iterator<Item = f64> + '_ {
+        std::iter::once(self.global).chain(self.categories.values().copied())
+    }
+}
+
+/// 按物品的市场价格倍率。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceMultiplier {
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    pub global: f64,
+    /// 各物品在全局倍率之上的倍率，未列出的物品为 1。
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub items: BTreeMap<Item, f64>,
+}
+
+impl Default for PriceMultiplier {
+    fn default() -> Self {
+        PriceMultiplier {
+            global: 1.0,
+            items: BTreeMap::new(),
+        }
+    }
+}
+
+impl PriceMultiplier {
+    pub fn get(&self, item: Item) -> f64 {
+        self.global * self.items.get(&item).copied().unwrap_or(1.0)
+    }
+
+    fn factors(&self) -> impl Iterator<Item = f64> + '_ {
+        std::iter::once(self.global).chain(self.items.values().copied())
+    }
+}
+
+fn days_per_month() -> f64 {
+    DAYS_PER_MONTH
+}
+
+fn is_days_per_month(x: &f64) -> bool {
+    *x == DAYS_PER_MONTH
+}
+
+/// 一套游戏设置。默认设置与数据文件一致。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// 每月的天数。
+    #[serde(default = "days_per_month", skip_serializing_if = "is_days_per_month")]
+    pub month_length: f64,
+    /// 建筑价格。
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub construction: Multiplier,
+    /// 维护费。
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub upkeep: Multiplier,
+    /// 生产速度。
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub speed: Multiplier,
+    /// 市场价格，同时影响出售和购买。
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub market: PriceMultiplier,
+}
+
+fn is_default<T: Default + PartialEq>(x: &T) -> bool {
+    *x == T::default()
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            month_length: DAYS_PER_MONTH,
+            construction: Multiplier::default(),
+            upkeep: Multiplier::default(),
+            speed: Multiplier::default(),
+            market: PriceMultiplier::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn is_default(&self) -> bool {
+        is_default(self)
+    }
+
+    /// 月长为正，各倍率非负。
+    pub fn check(&self) -> Result<()> {
+        if !(self.month_length > 0.0 && self.month_length.is_finite()) {
+            return Err(ErrorImpl::InvalidSettings(format!(
+                "month length {} must be positive",
+                self.month_length
+            ))
+            .into());
+        }
+        let factors = self
+            .construction
+            .factors()
+            .chain(self.upkeep.factors())
+            .chain(self.speed.factors())
+            .chain(self.market.factors());
+        for factor in factors {
+            if !(factor >= 0.0 && factor.is_finite()) {
+                return Err(ErrorImpl::InvalidSettings(format!(
+                    "multiplier {} must not be negative",
+                    factor
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// 按设置调整后的市场价格。
+    pub fn price(&self, item: Item) -> Result<Money> {
+        Ok(item.price()? * self.market.get(item))
+    }
+}
+
+#[test]
+fn settings_test() {
+    use super::building::types;
+    let settings = Settings {
+        month_length: 45.0,
+        construction: Multiplier {
+            global: 2.0,
+            categories: BTreeMap::from([(Category::Farm, 0.5)]),
+        },
+        market: PriceMultiplier {
+            global: 1.0,
+            items: BTreeMap::from([(Item::Wood, 3.0)]),
+        },
+        ..Default::default()
+    };
+    let farm = Type::Farm(types::Farm::Orchard);
+    let lumberyard = Type::Collector(types::Collector::Lumberyard);
+    assert_eq!(settings.construction.get(farm.into()), 1.0);
+    assert_eq!(settings.construction.get(lumberyard.into()), 2.0);
+    assert_eq!(settings.upkeep.get(lumberyard.into()), 1.0);
+    assert_eq!(
+        settings.price(Item::Wood).unwrap(),
+        Item::Wood.price().unwrap() * 3.0
+    );
+    assert!(settings.check().is_ok());
+
+    // 只保存与默认值不同的部分
+    let raw = serde_yaml::to_string(&settings).unwrap();
+    assert!(!raw.contains("upkeep"));
+    let parsed: Settings = serde_yaml::from_str(&raw).unwrap();
+    assert_eq!(parsed, settings);
+    assert!(serde_yaml::from_str::<Settings>("{}").unwrap().is_default());
+
+    let bad = Settings {
+        month_length: 0.0,
+        ..Default::default()
+    };
+    assert!(bad.check().is_err());
+}
@@ -0,0 +1,205 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 按目标产量反推整条生产链。
+
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    building::{types::Type, OutbuildingAmount, WorkerWage},
+    productivity::{Productivity, Speed},
+    recipe::{Id, Item},
+    research::Unlocks,
+    settings::{Category, Settings},
+    source, Condition, Result,
+};
+
+/// 生产链中使用的默认工资。
+pub const DEFAULT_WAGE: WorkerWage = WorkerWage::PERCENT_100;
+/// 生产链中使用的默认附属建筑数量。
+pub const DEFAULT_OUTBUILDINGS: OutbuildingAmount = OutbuildingAmount::Five;
+
+/// 为某物品选定的生产方式。
+struct Choice {
+    building_type: Type,
+    recipe_id: Id,
+    /// 单座建筑按游戏设置调整速度后的产能。
+    productivity: Productivity,
+}
+
+struct Solver<'a> {
+    settings: &'a Settings,
+    unlocks: Unlocks<'a>,
+    choices: HashMap<Item, Choice>,
+    /// 生产者在前、消费者在后的顺序。
+    order: Vec<Item>,
+    visiting: HashSet<Item>,
+}
+
+impl Solver<'_> {
+    /// 深度优先地为物品及其所有原料选定单位成本最低的生产方式。没有生产方式的物品从市场购买。
+    /// 循环依赖中回到已在栈上的物品时，该原料视为从市场购买。
+    fn visit(&mut self, item: Item) -> Result<()> {
+        if self.choices.contains_key(&item) || !self.visiting.insert(item) {
+            return Ok(());
+        }
+        let cheapest = source::sources(
+            item,
+            DEFAULT_WAGE,
+            DEFAULT_OUTBUILDINGS,
+            self.unlocks,
+            self.settings,
+        )
+        .into_iter()
+        .next();
+        if let Some(source) = cheapest {
+            let speed = self
+                .settings
+                .speed
+                .get(Category::from(source.building_type));
+            let productivity = source.condition(1).productivity()? * speed;
+            let inputs = productivity
+                .iter()
+                .filter(|(_, speed)| speed.per_month(self.settings.month_length) < 0.0)
+                .map(|(item, _)| *item)
+                .collect::<Vec<_>>();
+            for input in inputs {
+                self.visit(input)?;
+            }
+            self.choices.insert(
+                item,
+                Choice {
+                    building_type: source.building_type,
+                    recipe_id: source.recipe_id,
+                    productivity,
+                },
+            );
+            self.order.push(item);
+        }
+        self.visiting.remove(&item);
+        Ok(())
+    }
+}
+
+/// 求出以 `monthly_rate` 每月生产 `target` 所需的全部条件，原料一直追溯到原始资源。
+/// 只使用 `unlocks` 允许的建筑和配方，没有可用生产方式的原料从市场购买。
+/// 月长和各类建筑的生产速度取自 `settings`。返回的条件按生产者在前的顺序排列。
+pub fn solve(
+    target: Item,
+    monthly_rate: f64,
+    settings: &Settings,
+    unlocks: Unlocks,
+) -> Result<Vec<Condition>> {
+    settings.check()?;
+    let month_length = settings.month_length;
+    let mut solver = Solver {
+        settings,
+        unlocks,
+        choices: HashMap::new(),
+        order: vec![],
+        visiting: HashSet::new(),
+    };
+    solver.visit(target)?;
+
+    // 尚未满足的需求，按精确速率累计
+    let mut demand = Productivity::default();
+    demand.set(target, Speed::from_f64(monthly_rate / month_length)?);
+    let mut counts: HashMap<Item, u32> = HashMap::new();
+    for item in solver.order.iter().rev() {
+        let needed = demand
+            .get(*item)
+            .map_or(0.0, |speed| speed.per_month(month_length));
+        let choice = &solver.choices[item];
+        let per_building = choice
+            .productivity
+            .get(*item)
+            .map_or(0.0, |speed| speed.per_month(month_length));
+        if needed <= 0.0 || per_building <= 0.0 {
+            continue;
+        }
+        let count = (needed / per_building - 1e-9).ceil() as u32;
+        counts.insert(*item, count);
+        // 原料增加需求，副产品抵消需求；本物品的需求已经满足，不再扣减
+        let mut flow = choice.productivity.clone() * count as f64;
+        flow.set(*item, Speed::ZERO);
+        demand -= flow;
+    }
+
+    let mut conditions = vec![];
+    for item in solver.order.iter() {
+        let Some(&count) = counts.get(item) else {
+            continue;
+        };
+        let choice = &solver.choices[item];
+        let mut remaining = count;
+        while remaining > 0 {
+            let amount = remaining.min(u8::MAX as u32);
+            conditions.push(Condition::new(
+                choice.building_type,
+                choice.recipe_id.clone(),
+                DEFAULT_WAGE,
+                DEFAULT_OUTBUILDINGS,
+                amount as u8,
+            ));
+            remaining -= amount;
+        }
+    }
+    Ok(conditions)
+}
+
+#[test]
+fn solve_test() {
+    let slow = Settings {
+        month_length: 60.0,
+        speed: super::settings::Multiplier {
+            global: 0.25,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut buildings = vec![];
+    for settings in [Settings::default(), slow] {
+        let conditions = solve(Item::Dollhouse, 10.0, &settings, Unlocks::default()).unwrap();
+        let report = super::Simulator::from_conditions(&conditions)
+            .unwrap()
+            .with_settings(settings)
+            .simulate()
+            .unwrap();
+        for (item, speed) in report.productivity().iter() {
+            if *item == Item::Dollhouse {
+                assert!(speed.per_month(report.month_length()) >= 10.0);
+            } else if !source::producers(*item, Unlocks::default()).is_empty() {
+                assert!(
+                    speed.per_month(report.month_length()) > -1e-9,
+                    "{:?} is short",
+                    item
+                );
+            }
+        }
+        buildings.push(conditions.iter().map(|c| c.amount() as u32).sum::<u32>());
+    }
+    // 月份加倍而速度降为四分之一，每座建筑的月产量减半，需要更多建筑
+    assert!(buildings[1] > buildings[0]);
+
+    // 未研究冶炼时钢材只能购买
+    let nothing = std::collections::BTreeSet::new();
+    let conditions = solve(
+        Item::Steel,
+        10.0,
+        &Settings::default(),
+        Unlocks::new(Some(&nothing)),
+    )
+    .unwrap();
+    assert!(conditions.is_empty());
+}
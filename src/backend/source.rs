@@ -0,0 +1,216 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 比较能生产同一物品的不同建筑和配方。价格、速度和月长按给定的游戏设置计算。
+
+use super::{
+    building::{info, types::Type, OutbuildingAmount, WorkerWage},
+    money::Money,
+    recipe::{self, Id, Item},
+    research::Unlocks,
+    settings::{Category, Settings},
+    Condition, Result,
+};
+
+/// 能生产某物品的所有已解锁的建筑和配方，按建筑类型和配方排序。
+pub fn producers(item: Item, unlocks: Unlocks) -> Vec<(Type, Id)> {
+    let mut result = info::infos()
+        .iter()
+        .flat_map(|(building_type, info)| {
+            info.recipes()
+                .iter()
+                .map(move |id| (*building_type, id.clone()))
+        })
+        .filter(|(building_type, id)| unlocks.building(*building_type) && unlocks.recipe(id))
+        .filter(|(_, id)| {
+            recipe::get(id).is_ok_and(|recipe| recipe.outputs().any(|(x, _)| x == item))
+        })
+        .collect::<Vec<_>>();
+    result.sort();
+    result
+}
+
+/// 一种生产方式：单座建筑在给定工资和附属建筑数量下生产某物品的成本。
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub building_type: Type,
+    pub recipe_id: Id,
+    pub worker_wage: WorkerWage,
+    pub outbuilding_amount: OutbuildingAmount,
+    /// 每月产出该物品的数量。
+    pub monthly_output: f64,
+    /// 建筑价格，包括附属建筑。
+    pub price: Money,
+    pub monthly_upkeep: Money,
+    /// 按市场价购买原料的每月费用。
+    pub monthly_input_cost: Money,
+    /// 副产品按市场价出售的每月收入，没有价格的副产品不计。
+    pub monthly_byproduct_value: Money,
+}
+
+impl Source {
+    /// 每单位产品的运营成本（维护费和原料费，扣除副产品收入）。
+    pub fn unit_cost(&self) -> f64 {
+        (self.monthly_upkeep + self.monthly_input_cost - self.monthly_byproduct_value).value()
+            as f64
+            / self.monthly_output
+    }
+
+    /// 每单位月产能所需的建筑投资。
+    pub fn capital_per_unit(&self) -> f64 {
+        self.price.value() as f64 / self.monthly_output
+    }
+
+    pub fn condition(&self, amount: u8) -> Condition {
+        Condition::new(
+            self.building_type,
+            self.recipe_id.clone(),
+            self.worker_wage,
+            self.outbuilding_amount,
+            amount,
+        )
+    }
+}
+
+/// 单座建筑按 `recipe_id` 生产 `item` 的成本。不产出该物品时为 `None`。
+fn source(
+    item: Item,
+    building_type: Type,
+    recipe_id: Id,
+    worker_wage: WorkerWage,
+    outbuilding_amount: OutbuildingAmount,
+    settings: &Settings,
+) -> Result<Option<Source>> {
+    let category = Category::from(building_type);
+    let condition = Condition::new(
+        building_type,
+        recipe_id.clone(),
+        worker_wage,
+        outbuilding_amount,
+        1,
+    );
+    let productivity = condition.productivity()? * settings.speed.get(category);
+    let monthly_output = productivity
+        .get(item)
+        .map_or(0.0, |speed| speed.per_month(settings.month_length));
+    if monthly_output <= 0.0 {
+        return Ok(None);
+    }
+    let (outputs, _) = productivity.split();
+    let mut monthly_byproduct_value = Money::zero();
+    for (other, speed) in outputs.iter() {
+        if *other == item {
+            continue;
+        }
+        if let Ok(price) = settings.price(*other) {
+            monthly_byproduct_value += speed.monthly_value(price, settings.month_length);
+        }
+    }
+    Ok(Some(Source {
+        building_type,
+        recipe_id,
+        worker_wage,
+        outbuilding_amount,
+        monthly_output,
+        price: condition.price()? * settings.construction.get(category),
+        monthly_upkeep: condition.upkeep()? * settings.upkeep.get(category),
+        monthly_input_cost: productivity.estimated_monthly_material_cost(settings)?,
+        monthly_byproduct_value,
+    }))
+}
+
+/// 列出所有能生产 `item` 的已解锁建筑和配方及其成本，按单位成本从低到高排序。
+/// 无法计算成本的生产方式（例如原料没有市场价）被跳过。
+pub fn sources(
+    item: Item,
+    worker_wage: WorkerWage,
+    outbuilding_amount: OutbuildingAmount,
+    unlocks: Unlocks,
+    settings: &Settings,
+) -> Vec<Source> {
+    let mut result = producers(item, unlocks)
+        .into_iter()
+        .filter_map(|(building_type, recipe_id)| {
+            source(
+                item,
+                building_type,
+                recipe_id,
+                worker_wage,
+                outbuilding_amount,
+                settings,
+            )
+            .ok()
+            .flatten()
+        })
+        .collect::<Vec<_>>();
+    result.sort_by(|a, b| a.unit_cost().total_cmp(&b.unit_cost()));
+    result
+}
+
+#[test]
+fn sources_test() {
+    let sources = sources(
+        Item::Water,
+        WorkerWage::PERCENT_100,
+        OutbuildingAmount::Five,
+        Unlocks::default(),
+        &Settings::default(),
+    );
+    let types = sources.iter().map(|s| s.building_type).collect::<Vec<_>>();
+    assert!(types.contains(&Type::Collector(
+        super::building::types::Collector::WaterSiphon
+    )));
+    assert!(types.contains(&Type::Collector(
+        super::building::types::Collector::WaterWell
+    )));
+    assert!(sources
+        .windows(2)
+        .all(|w| w[0].unit_cost() <= w[1].unit_cost()));
+
+    // 副产品收入从单位成本中扣除
+    let with_byproduct = Source {
+        monthly_byproduct_value: Money::from(100),
+        ..sources[0].clone()
+    };
+    assert!(with_byproduct.unit_cost() < sources[0].unit_cost());
+
+    // 按设置调整速度、维护费和月长
+    let settings = Settings {
+        month_length: 60.0,
+        speed: super::settings::Multiplier {
+            global: 2.0,
+            ..Default::default()
+        },
+        upkeep: super::settings::Multiplier {
+            global: 8.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let adjusted = self::sources(
+        Item::Water,
+        WorkerWage::PERCENT_100,
+        OutbuildingAmount::Five,
+        Unlocks::default(),
+        &settings,
+    );
+    let first = &sources[0];
+    let same = adjusted
+        .iter()
+        .find(|s| s.building_type == first.building_type && s.recipe_id == first.recipe_id)
+        .unwrap();
+    assert!((same.monthly_output - first.monthly_output * 4.0).abs() < 1e-6);
+    assert_eq!(same.monthly_upkeep, first.monthly_upkeep * 8);
+    assert!(same.unit_cost() > first.unit_cost());
+}
@@ -0,0 +1,393 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 逐日模拟。每座建筑有输入和输出缓冲区，原料齐全时才开始一批生产，`day_to_gen` 天后完成。
+//! 与按平均速率求和的 `Simulator` 不同，它能反映开工延迟和瓶颈。
+//!
+//! 每天依次进行：各建筑从仓库补充原料（规划内不能自给的原料按市场价购买），生产，
+//! 把产出运回仓库，最后卖掉仓库中超出各使用者缓冲区容量的部分。
+//! 价格、维护费、生产速度和月长与 `Simulator` 一样按规划的游戏设置计算。
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::{
+    building::Building,
+    money::Money,
+    productivity::Productivity,
+    recipe::{Item, Recipe},
+    settings::{Category, Settings},
+    Condition, ErrorImpl, Result,
+};
+
+/// 浮点误差容限。
+const EPSILON: f64 = 1e-9;
+/// 最多模拟的天数，每天的记录都保存在内存中。
+pub const MAX_DAYS: u32 = 3600;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// 模拟的天数。
+    pub days: u32,
+    /// 是否从市场购买规划内不能自给的原料。为假时缺少的原料只能等待。
+    pub buy_inputs: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            days: 360,
+            buy_inputs: true,
+        }
+    }
+}
+
+/// 某一天结束时的状态。
+#[derive(Debug, Clone, Default)]
+pub struct Day {
+    pub day: u32,
+    /// 仓库和各建筑缓冲区中的物品总数。
+    pub inventory: BTreeMap<Item, u64>,
+    /// 当天完成的产出。
+    pub produced: BTreeMap<Item, u64>,
+    pub bought: BTreeMap<Item, u64>,
+    pub sold: BTreeMap<Item, u64>,
+    /// 当天因缺少原料而停工的建筑数量。
+    pub starved: u32,
+    /// 累计现金流，第 0 天为 `-total_price`。
+    pub cash: Money,
+}
+
+/// 逐日模拟的结果。
+#[derive(Debug)]
+pub struct Timeline {
+    days: Vec<Day>,        // 第 0 天为初始状态
+    utilization: Vec<f64>, // 与条件一一对应
+}
+
+impl Timeline {
+    pub fn days(&self) -> &[Day] {
+        &self.days
+    }
+    /// 各条件的建筑实际生产时间占可生产时间的比例。
+    pub fn utilization(&self) -> &[f64] {
+        &self.utilization
+    }
+    /// 累计现金流首次不为负的日子。在模拟期内没有回本时为 `None`。
+    pub fn break_even_day(&self) -> Option<u32> {
+        self.days
+            .iter()
+            .skip(1)
+            .find(|day| day.cash >= Money::zero())
+            .map(|day| day.day)
+    }
+}
+
+/// 一座建筑的生产状态。
+struct Station {
+    condition: usize,
+    building: Box<dyn Building>,
+    recipe: &'static Recipe,
+    step: f64,     // 每天能完成的批次数
+    capacity: u64, // 输入缓冲区能容纳的批次数
+    inputs: HashMap<Item, u64>,
+    outputs: HashMap<Item, u64>,
+    running: bool,
+    progress: f64, // 当前批次的完成度
+}
+
+impl Station {
+    /// `speed` 为设置中的速度倍率。`day_to_gen` 为零的配方一天内能完成无穷多批，返回错误。
+    fn new(
+        condition: usize,
+        building: Box<dyn Building>,
+        recipe: &'static Recipe,
+        speed: f64,
+    ) -> Result<Station> {
+        if recipe.day_to_gen() == 0 {
+            return Err(ErrorImpl::ZeroDayToGen.into());
+        }
+        let step = building.rate() * speed / recipe.day_to_gen() as f64;
+        Ok(Station {
+            condition,
+            building,
+            recipe,
+            step,
+            capacity: step.ceil() as u64 + 1,
+            inputs: HashMap::new(),
+            outputs: HashMap::new(),
+            running: false,
+            progress: 0.0,
+        })
+    }
+
+    /// 原料齐全时取出一批原料并开工。
+    fn try_start(&mut self) -> bool {
+        let recipe = self.recipe;
+        let ready = recipe
+            .inputs()
+            .all(|(item, amount)| self.inputs.get(&item).copied().unwrap_or(0) >= amount);
+        if ready {
+            for (item, amount) in recipe.inputs() {
+                *self.inputs.entry(item).or_insert(0) -= amount;
+            }
+            self.running = true;
+        }
+        ready
+    }
+
+    /// 生产一天，返回实际用掉的批次数以及是否因缺少原料而停工。
+    fn work(&mut self, produced: &mut BTreeMap<Item, u64>) -> (f64, bool) {
+        let mut budget = self.step;
+        let mut starved = false;
+        while budget > EPSILON {
+            if !self.running && !self.try_start() {
+                starved = true;
+                break;
+            }
+            let need = 1.0 - self.progress;
+            if budget + EPSILON >= need {
+                budget = (budget - need).max(0.0);
+                self.progress = 0.0;
+                self.running = false;
+                for (item, amount) in self.recipe.outputs() {
+                    *self.outputs.entry(item).or_insert(0) += amount;
+                    *produced.entry(item).or_insert(0) += amount;
+                }
+            } else {
+                self.progress += budget;
+                budget = 0.0;
+            }
+        }
+        (self.step - budget, starved)
+    }
+}
+
+fn add(map: &mut BTreeMap<Item, u64>, item: Item, amount: u64) {
+    if amount > 0 {
+        *map.entry(item).or_insert(0) += amount;
+    }
+}
+
+/// 按条件和游戏设置逐日模拟 `options.days` 天。天数超过 `MAX_DAYS` 时返回错误。
+pub fn run(conditions: &[Condition], settings: &Settings, options: Options) -> Result<Timeline> {
+    if options.days > MAX_DAYS {
+        return Err(ErrorImpl::TooManyDays(options.days).into());
+    }
+    settings.check()?;
+    let mut stations = vec![];
+    let mut total_price = Money::zero();
+    let mut daily_upkeep = 0.0;
+    for (i, cond) in conditions.iter().enumerate() {
+        for _ in 0..cond.amount() {
+            let building = cond.create_building()?;
+            let category = Category::from(building.plant_type());
+            total_price += building.price() * settings.construction.get(category);
+            daily_upkeep += (building.upkeep() * settings.upkeep.get(category)).value() as f64
+                / settings.month_length;
+            // 没有配方的辅助建筑只计入价格和维护费
+            if let Some(recipe) = building.recipe() {
+                let speed = settings.speed.get(category);
+                stations.push(Station::new(i, building, recipe, speed)?);
+            }
+        }
+    }
+
+    // 只购买按平均速率计算不能自给的原料，能自给的原料等待规划内的建筑生产
+    let net = stations
+        .iter()
+        .map(|s| {
+            let category = Category::from(s.building.plant_type());
+            Ok(s.building.productivity()? * settings.speed.get(category))
+        })
+        .sum::<Result<Productivity>>()?;
+    let mut prices = HashMap::new();
+    let mut reserve: HashMap<Item, u64> = HashMap::new();
+    for s in stations.iter() {
+        let recipe = s.recipe;
+        for (item, amount) in recipe.inputs() {
+            *reserve.entry(item).or_insert(0) += amount * s.capacity;
+        }
+        for (item, _) in recipe.inputs().chain(recipe.outputs()) {
+            prices.insert(item, settings.price(item)?.value() as f64);
+        }
+    }
+    let buyable = |item: Item| net.get(item).is_none_or(|s| s.is_negative());
+
+    let mut stock: HashMap<Item, u64> = HashMap::new();
+    let mut cash = -(total_price.value() as f64);
+    let mut used = vec![0.0; conditions.len()];
+    let mut available = vec![0.0; conditions.len()];
+    let mut days = vec![Day {
+        cash: Money::from(cash as i64),
+        ..Default::default()
+    }];
+    for day in 1..=options.days {
+        let mut record = Day {
+            day,
+            ..Default::default()
+        };
+        for s in stations.iter_mut() {
+            for (item, amount) in s.recipe.inputs() {
+                let want = amount * s.capacity;
+                let have = s.inputs.entry(item).or_insert(0);
+                if *have >= want {
+                    continue;
+                }
+                let in_stock = stock.entry(item).or_insert(0);
+                let take = (want - *have).min(*in_stock);
+                *in_stock -= take;
+                *have += take;
+                if *have < want && options.buy_inputs && buyable(item) {
+                    let buy = want - *have;
+                    *have += buy;
+                    cash -= prices[&item] * buy as f64;
+                    add(&mut record.bought, item, buy);
+                }
+            }
+        }
+        for s in stations.iter_mut() {
+            let (work, starved) = s.work(&mut record.produced);
+            used[s.condition] += work;
+            available[s.condition] += s.step;
+            if starved {
+                record.starved += 1;
+            }
+        }
+        for s in stations.iter_mut() {
+            for (item, amount) in s.outputs.drain() {
+                *stock.entry(item).or_insert(0) += amount;
+            }
+        }
+        for (item, amount) in stock.iter_mut() {
+            let keep = reserve.get(item).copied().unwrap_or(0);
+            if *amount > keep {
+                let sell = *amount - keep;
+                *amount = keep;
+                cash += prices[item] * sell as f64;
+                add(&mut record.sold, *item, sell);
+            }
+        }
+        cash -= daily_upkeep;
+        for (item, amount) in stock.iter() {
+            add(&mut record.inventory, *item, *amount);
+        }
+        for s in stations.iter() {
+            for (item, amount) in s.inputs.iter().chain(s.outputs.iter()) {
+                add(&mut record.inventory, *item, *amount);
+            }
+        }
+        record.cash = Money::from(cash.round() as i64);
+        days.push(record);
+    }
+
+    let utilization = used
+        .iter()
+        .zip(available.iter())
+        .map(|(used, available)| {
+            if *available > 0.0 {
+                used / available
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    Ok(Timeline { days, utilization })
+}
+
+#[test]
+fn timeline_test() {
+    use super::{wood_chain, Simulator};
+    let conditions = wood_chain();
+    let options = Options {
+        days: 360,
+        buy_inputs: false,
+    };
+    let timeline = run(&conditions, &Settings::default(), options).unwrap();
+    assert_eq!(timeline.days().len(), 361);
+    // 第一天木工中心还没有木材
+    assert_eq!(timeline.days()[1].starved, 1);
+    assert!(timeline.days()[1].bought.is_empty());
+
+    let report = Simulator::from_conditions(&conditions)
+        .unwrap()
+        .simulate()
+        .unwrap();
+    let expected = report
+        .produced()
+        .get(Item::WoodenPlanks)
+        .unwrap()
+        .per_month(report.month_length())
+        * 12.0;
+    let produced = timeline
+        .days()
+        .iter()
+        .filter_map(|day| day.produced.get(&Item::WoodenPlanks))
+        .sum::<u64>() as f64;
+    assert!(produced <= expected + 1e-6);
+    assert!(produced >= expected * 0.9, "{} of {}", produced, expected);
+    assert!(timeline.utilization()[0] > 0.999);
+
+    let too_long = Options {
+        days: MAX_DAYS + 1,
+        ..options
+    };
+    assert!(run(&conditions, &Settings::default(), too_long).is_err());
+}
+
+#[test]
+fn timeline_settings_test() {
+    use super::{settings::Multiplier, wood_chain, Simulator};
+    let conditions = wood_chain();
+    let settings = Settings {
+        month_length: 60.0,
+        construction: Multiplier {
+            global: 2.0,
+            ..Default::default()
+        },
+        speed: Multiplier {
+            global: 2.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let options = Options {
+        days: 360,
+        buy_inputs: false,
+    };
+    let timeline = run(&conditions, &settings, options).unwrap();
+    let report = Simulator::from_conditions(&conditions)
+        .unwrap()
+        .with_settings(settings.clone())
+        .simulate()
+        .unwrap();
+    // 建筑投资和产量与按同样设置计算的报告一致
+    assert_eq!(
+        timeline.days()[0].cash,
+        Money::zero() - report.total_price()
+    );
+    let expected = report
+        .produced()
+        .get(Item::WoodenPlanks)
+        .unwrap()
+        .per_month(report.month_length())
+        * 360.0
+        / settings.month_length;
+    let produced = timeline
+        .days()
+        .iter()
+        .filter_map(|day| day.produced.get(&Item::WoodenPlanks))
+        .sum::<u64>() as f64;
+    assert!(produced <= expected + 1e-6);
+    assert!(produced >= expected * 0.9, "{} of {}", produced, expected);
+}
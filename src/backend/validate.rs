@@ -0,0 +1,226 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 数据文件一致性检查。
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+};
+
+use super::{
+    building::{types::Type, Curve},
+    data::GameData,
+    logistics::Vehicle,
+    recipe::{Id, Item},
+    research::Node,
+};
+
+/// 数据文件中的一处问题。
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Problem {
+    /// building_info.yaml 中缺少该建筑类型。
+    MissingInfo(Type),
+    /// building_info.yaml 中该建筑的信息种类与类型不一致。
+    WrongInfoKind(Type),
+    /// 建筑引用的配方不在 recipes.yaml 中。
+    UnknownRecipe(Type, Id),
+    /// 物品在 global_market_prices.yaml 中没有价格。
+    MissingPrice(Item),
+    /// 配方的 `day_to_gen` 为零。
+    ZeroDayToGen(Id),
+    /// 没有任何建筑使用该配方。
+    UnusedRecipe(Id),
+    /// wages.yaml 中工资范围为空或包含零。
+    BadWageRange(u16, u16),
+    /// wages.yaml 中的曲线没有点或倍率为负。工资的顺序在加载时已经检查。
+    BadWageCurve(&'static str),
+    /// vehicles.yaml 中缺少该车辆。
+    MissingVehicle(Vehicle),
+    /// vehicles.yaml 中该车辆的容量不是正数或费用为负。
+    BadVehicle(Vehicle),
+    /// research.yaml 中的节点解锁的配方不在 recipes.yaml 中。
+    UnknownResearchRecipe(String, Id),
+    /// research.yaml 中的节点的前置节点不存在。
+    UnknownPrerequisite(String, String),
+    /// research.yaml 中的节点直接或间接地以自己为前置节点。
+    ResearchCycle(String),
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::MissingInfo(t) => {
+                write!(f, "building_info.yaml: no entry for building {:?}", t)
+            }
+            Problem::WrongInfoKind(t) => {
+                write!(
+                    f,
+                    "building_info.yaml: entry for {:?} has the wrong kind",
+                    t
+                )
+            }
+            Problem::UnknownRecipe(t, id) => write!(
+                f,
+                "building_info.yaml: {:?} uses recipe \"{}\" which is not in recipes.yaml",
+                t, id
+            ),
+            Problem::MissingPrice(item) => {
+                write!(f, "global_market_prices.yaml: no price for {:?}", item)
+            }
+            Problem::ZeroDayToGen(id) => {
+                write!(f, "recipes.yaml: recipe \"{}\" has day_to_gen 0", id)
+            }
+            Problem::UnusedRecipe(id) => write!(
+                f,
+                "recipes.yaml: recipe \"{}\" is not used by any building",
+                id
+            ),
+            Problem::BadWageRange(min, max) => {
+                write!(f, "wages.yaml: wage range {}%-{}% is invalid", min, max)
+            }
+            Problem::BadWageCurve(name) => write!(
+                f,
+                "wages.yaml: curve \"{}\" must have points with non-negative factors",
+                name
+            ),
+            Problem::MissingVehicle(vehicle) => {
+                write!(f, "vehicles.yaml: no entry for {}", vehicle)
+            }
+            Problem::BadVehicle(vehicle) => write!(
+                f,
+                "vehicles.yaml: {} must have a positive capacity and a non-negative cost",
+                vehicle
+            ),
+            Problem::UnknownResearchRecipe(node, id) => write!(
+                f,
+                "research.yaml: {} unlocks recipe \"{}\" which is not in recipes.yaml",
+                node, id
+            ),
+            Problem::UnknownPrerequisite(node, prerequisite) => write!(
+                f,
+                "research.yaml: {} requires unknown node \"{}\"",
+                node, prerequisite
+            ),
+            Problem::ResearchCycle(node) => {
+                write!(f, "research.yaml: {} depends on itself", node)
+            }
+        }
+    }
+}
+
+/// 曲线至少有一个点，倍率非负。
+fn curve_ok(curve: &Curve) -> bool {
+    !curve.points().is_empty() && curve.points().iter().all(|(_, factor)| *factor >= 0.0)
+}
+
+/// 节点是否直接或间接地以自己为前置节点。
+fn in_cycle(research: &BTreeMap<String, Node>, name: &str) -> bool {
+    let mut stack = vec![name];
+    let mut seen = HashSet::new();
+    while let Some(current) = stack.pop() {
+        let Some(node) = research.get(current) else {
+            continue;
+        };
+        for prerequisite in node.requires.iter() {
+            if prerequisite == name {
+                return true;
+            }
+            if seen.insert(prerequisite.as_str()) {
+                stack.push(prerequisite);
+            }
+        }
+    }
+    false
+}
+
+/// 检查数据的一致性，返回按种类排序的问题列表。
+pub fn validate(data: &GameData) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut used_recipes = HashSet::new();
+    for building_type in Type::all() {
+        let Some(info) = data.infos.get(&building_type) else {
+            problems.push(Problem::MissingInfo(building_type));
+            continue;
+        };
+        if !info.matches(building_type) {
+            problems.push(Problem::WrongInfoKind(building_type));
+        }
+        for id in info.recipes() {
+            used_recipes.insert(id);
+            if !data.recipes.contains_key(id) {
+                problems.push(Problem::UnknownRecipe(building_type, id.clone()));
+            }
+        }
+    }
+    for item in enum_iterator::all::<Item>() {
+        if !data.prices.contains_key(&item) {
+            problems.push(Problem::MissingPrice(item));
+        }
+    }
+    for (id, recipe) in data.recipes.iter() {
+        if recipe.day_to_gen() == 0 {
+            problems.push(Problem::ZeroDayToGen(id.clone()));
+        }
+        if !used_recipes.contains(id) {
+            problems.push(Problem::UnusedRecipe(id.clone()));
+        }
+    }
+    if data.wages.min == 0 || data.wages.min > data.wages.max {
+        problems.push(Problem::BadWageRange(data.wages.min, data.wages.max));
+    }
+    for (name, curve) in [("speed", &data.wages.speed), ("upkeep", &data.wages.upkeep)] {
+        if !curve_ok(curve) {
+            problems.push(Problem::BadWageCurve(name));
+        }
+    }
+    for vehicle in enum_iterator::all::<Vehicle>() {
+        match data.vehicles.get(&vehicle) {
+            None => problems.push(Problem::MissingVehicle(vehicle)),
+            Some(info) if info.capacity <= 0.0 || info.cost_per_distance.value() < 0 => {
+                problems.push(Problem::BadVehicle(vehicle))
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, node) in data.research.iter() {
+        for id in node.recipes.iter() {
+            if !data.recipes.contains_key(id) {
+                problems.push(Problem::UnknownResearchRecipe(name.clone(), id.clone()));
+            }
+        }
+        for prerequisite in node.requires.iter() {
+            if !data.research.contains_key(prerequisite) {
+                problems.push(Problem::UnknownPrerequisite(
+                    name.clone(),
+                    prerequisite.clone(),
+                ));
+            }
+        }
+        if in_cycle(&data.research, name) {
+            problems.push(Problem::ResearchCycle(name.clone()));
+        }
+    }
+    problems.sort();
+    problems
+}
+
+#[test]
+fn embedded_data_test() {
+    let problems = validate(&GameData::embedded());
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    assert!(problems.is_empty());
+}
@@ -0,0 +1,139 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 工资建议：对规划中的每一行，保持其他行不变，尝试允许范围内的各档工资，找出利润最高的一档。
+//!
+//! 以整个规划的月利润衡量一行的贡献：该行的产出无论出售还是供给其他行都按实际用途计价，
+//! 限制原料供给时，下游因此多开工或少开工的影响也计算在内。
+
+use super::{
+    building::WorkerWage, data, money::Money, plan::Plan, Condition, Options, Report, Result,
+    Simulator,
+};
+
+/// 尝试的工资间隔（百分点）。允许范围的两端和预设档位总会被尝试。
+pub const STEP: u16 = 5;
+
+/// 对某一行的建议。
+#[derive(Debug, Clone)]
+pub struct Advice {
+    /// 该行在条件表中的位置。
+    pub index: usize,
+    pub current: WorkerWage,
+    pub best: WorkerWage,
+    /// 该行在当前工资下的边际利润。
+    pub current_profit: Money,
+    /// 该行在建议工资下的边际利润。
+    pub best_profit: Money,
+    /// 改用建议工资后规划月利润的增加。
+    pub gain: Money,
+}
+
+impl Advice {
+    /// 建议是否与当前工资不同。
+    pub fn changes(&self) -> bool {
+        self.best != self.current
+    }
+}
+
+/// 要尝试的工资，从低到高排列。
+fn wages(current: WorkerWage) -> Vec<WorkerWage> {
+    let wages = &data::current().wages;
+    let mut result = (wages.min..=wages.max)
+        .step_by(STEP as usize)
+        .chain([wages.max])
+        .map(WorkerWage::new)
+        .chain(WorkerWage::PRESETS)
+        .chain([current])
+        .filter(|wage| (wages.min..=wages.max).contains(&wage.percent()))
+        .collect::<Vec<_>>();
+    result.sort();
+    result.dedup();
+    result
+}
+
+fn evaluate(plan: &Plan, options: Options) -> Result<Report> {
+    Simulator::for_plan(plan)?.with_options(options).simulate()
+}
+
+/// 为每个有工人的行给出建议，没有工人的辅助建筑跳过。各行的建议是分别求得的，同时采用多条建议时效果未必等于各自增益之和。
+pub fn advise(plan: &Plan, options: Options) -> Result<Vec<Advice>> {
+    let report = evaluate(plan, options)?;
+    let base_profit = report.monthly_profit();
+    let mut result = vec![];
+    for (index, cond) in plan.conditions.iter().enumerate() {
+        let Some(current) = cond.worker_wage() else {
+            continue;
+        };
+        let current_profit = report.contributions()[index].profit();
+        let mut advice = Advice {
+            index,
+            current,
+            best: current,
+            current_profit,
+            best_profit: current_profit,
+            gain: Money::zero(),
+        };
+        let mut trial = plan.clone();
+        for wage in wages(current) {
+            trial.conditions[index].set_worker_wage(wage);
+            let Ok(report) = evaluate(&trial, options) else {
+                continue;
+            };
+            let gain = report.monthly_profit() - base_profit;
+            if gain > advice.gain {
+                advice.best = wage;
+                advice.best_profit = report.contributions()[index].profit();
+                advice.gain = gain;
+            }
+        }
+        result.push(advice);
+    }
+    Ok(result)
+}
+
+/// 采用所有建议。
+pub fn apply(conditions: &mut [Condition], advice: &[Advice]) {
+    for advice in advice.iter() {
+        if let Some(cond) = conditions.get_mut(advice.index) {
+            cond.set_worker_wage(advice.best);
+        }
+    }
+}
+
+#[test]
+fn wage_advisor_test() {
+    let mut conditions = super::wood_chain();
+    conditions[0].set_worker_wage(WorkerWage::new(60));
+    let options = Options { throttle: true };
+    let plan = Plan {
+        conditions,
+        ..Default::default()
+    };
+    let advice = advise(&plan, options).unwrap();
+    assert_eq!(advice.len(), 2);
+    assert_eq!(advice[0].current, WorkerWage::new(60));
+    assert!(advice.iter().all(|a| a.gain >= Money::zero()));
+    assert!(advice
+        .iter()
+        .all(|a| a.changes() == (a.gain > Money::zero())));
+
+    let before = evaluate(&plan, options).unwrap().monthly_profit();
+    for a in advice.iter() {
+        let mut single = plan.clone();
+        apply(&mut single.conditions, std::slice::from_ref(a));
+        let after = evaluate(&single, options).unwrap().monthly_profit();
+        assert_eq!(after - before, a.gain);
+    }
+}
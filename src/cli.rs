@@ -0,0 +1,1356 @@
+// Copyright 2023 Hapenia Lans
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! 命令行模式：不打开窗口，直接输出结果。
+//!
+//! ```text
+//! roi-calc [--data-dir <dir>] --validate
+//! roi-calc [--data-dir <dir>] eval <plan> [--throttle] [--format table|json|csv]
+//! roi-calc [--data-dir <dir>] timeline <plan> [--days <n>] [--no-buy] [--format table|json|csv]
+//! roi-calc [--data-dir <dir>] sensitivity <plan> [--range <percent>] [--steps <n>]
+//!     [--items <item,...>] [--throttle] [--format table|json|csv]
+//! roi-calc [--data-dir <dir>] montecarlo <plan> [--runs <n>] [--seed <n>]
+//!     [--price-noise <noise>] [--speed-noise <noise>] [--throttle] [--format table|json|csv]
+//! roi-calc [--data-dir <dir>] optimize --budget <money> [--objective profit|roi]
+//!     [--max-buildings <n>] [--allow <type,...>] [--researched <node,...>] [--throttle]
+//!     [--results <n>] [--save <plan>] [--format table|json|csv]
+//! roi-calc [--data-dir <dir>] wages <plan> [--throttle] [--save <plan>] [--format table|json|csv]
+//! roi-calc [--data-dir <dir>] makebuy <plan> [--horizon <months>] [--format table|json|csv]
+//! ```
+//!
+//! `<noise>` 为 `none`、`uniform:<percent>` 或 `normal:<percent>`。
+
+use std::{collections::BTreeMap, fmt::Write, path::Path};
+
+use serde_derive::Serialize;
+
+use crate::backend::{
+    self, balance,
+    building::types::Type,
+    data, make_or_buy,
+    money::Money,
+    monte_carlo::{self, Noise},
+    optimizer::{self, Objective},
+    plan::Plan,
+    recipe::Item,
+    research, sensitivity, timeline, validate, wage_advisor, ErrorImpl, Report, Simulator,
+};
+
+const VALIDATE_ARG: &str = "--validate";
+const FORMAT_ARG: &str = "--format";
+const DAYS_ARG: &str = "--days";
+const NO_BUY_ARG: &str = "--no-buy";
+const THROTTLE_ARG: &str = "--throttle";
+const RANGE_ARG: &str = "--range";
+const STEPS_ARG: &str = "--steps";
+const ITEMS_ARG: &str = "--items";
+const RUNS_ARG: &str = "--runs";
+const SEED_ARG: &str = "--seed";
+const PRICE_NOISE_ARG: &str = "--price-noise";
+const SPEED_NOISE_ARG: &str = "--speed-noise";
+const BUDGET_ARG: &str = "--budget";
+const OBJECTIVE_ARG: &str = "--objective";
+const MAX_BUILDINGS_ARG: &str = "--max-buildings";
+const ALLOW_ARG: &str = "--allow";
+const RESEARCHED_ARG: &str = "--researched";
+const RESULTS_ARG: &str = "--results";
+const SAVE_ARG: &str = "--save";
+const HORIZON_ARG: &str = "--horizon";
+const EVAL_COMMAND: &str = "eval";
+const TIMELINE_COMMAND: &str = "timeline";
+const SENSITIVITY_COMMAND: &str = "sensitivity";
+const MONTE_CARLO_COMMAND: &str = "montecarlo";
+const OPTIMIZE_COMMAND: &str = "optimize";
+const WAGES_COMMAND: &str = "wages";
+const MAKE_OR_BUY_COMMAND: &str = "makebuy";
+/// `optimize` 默认输出的规划数。
+const DEFAULT_RESULTS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+impl OutputFormat {
+    fn parse(s: &str) -> Option<OutputFormat> {
+        match s {
+            "table" => Some(OutputFormat::Table),
+            "json" => Some(OutputFormat::Json),
+            "csv" => Some(OutputFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// 解析后的命令行参数。
+struct Args {
+    validate: bool,
+    format: OutputFormat,
+    timeline: timeline::Options,
+    simulation: backend::Options,
+    /// 价格变化幅度，为比例而非百分数。
+    range: f64,
+    steps: u32,
+    items: Option<Vec<Item>>,
+    monte_carlo: monte_carlo::Config,
+    constraints: optimizer::Constraints,
+    results: usize,
+    save: Option<String>,
+    make_or_buy: make_or_buy::Config,
+    positional: Vec<String>,
+}
+
+fn parse_item(name: &str) -> Option<Item> {
+    enum_iterator::all::<Item>().find(|item| format!("{:?}", item) == name)
+}
+
+/// 接受 `Lumberyard` 或 `Collector(Lumberyard)` 两种写法。
+fn parse_type(name: &str) -> Option<Type> {
+    Type::all().find(|t| {
+        let full = format!("{:?}", t);
+        full == name || full.ends_with(&format!("({})", name))
+    })
+}
+
+/// 解析 `none`、`uniform:<percent>` 或 `normal:<percent>`。百分比须为有限的非负数。
+fn parse_noise(s: &str) -> Option<Noise> {
+    if s == "none" {
+        return Some(Noise::None);
+    }
+    let (kind, percent) = s.split_once(':')?;
+    let x = percent.parse::<f64>().ok()? / 100.0;
+    let noise = match kind {
+        "uniform" => Noise::Uniform(x),
+        "normal" => Noise::Normal(x),
+        _ => return None,
+    };
+    noise.check().is_ok().then_some(noise)
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, String> {
+        let mut result = Args {
+            validate: false,
+            format: OutputFormat::Table,
+            timeline: timeline::Options::default(),
+            simulation: backend::Options::default(),
+            range: sensitivity::DEFAULT_RANGE,
+            steps: sensitivity::DEFAULT_STEPS,
+            items: None,
+            monte_carlo: monte_carlo::Config::default(),
+            constraints: optimizer::Constraints::default(),
+            results: DEFAULT_RESULTS,
+            save: None,
+            make_or_buy: make_or_buy::Config::default(),
+            positional: vec![],
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                VALIDATE_ARG => result.validate = true,
+                data::DATA_DIR_ARG => {
+                    iter.next();
+                }
+                FORMAT_ARG => {
+                    let value = iter.next().ok_or("missing value for --format")?;
+                    result.format = OutputFormat::parse(value)
+                        .ok_or_else(|| format!("unknown output format \"{}\"", value))?;
+                }
+                DAYS_ARG => {
+                    let value = iter.next().ok_or("missing value for --days")?;
+                    result.timeline.days = value
+                        .parse()
+                        .map_err(|_| format!("invalid number of days \"{}\"", value))?;
+                }
+                NO_BUY_ARG => result.timeline.buy_inputs = false,
+                THROTTLE_ARG => result.simulation.throttle = true,
+                RANGE_ARG => {
+                    let value = iter.next().ok_or("missing value for --range")?;
+                    let percent = value
+                        .parse::<f64>()
+                        .map_err(|_| format!("invalid range \"{}\"", value))?;
+                    result.range = percent / 100.0;
+                }
+                STEPS_ARG => {
+                    let value = iter.next().ok_or("missing value for --steps")?;
+                    result.steps = value
+                        .parse()
+                        .map_err(|_| format!("invalid number of steps \"{}\"", value))?;
+                }
+                BUDGET_ARG => {
+                    let value = iter.next().ok_or("missing value for --budget")?;
+                    let budget = value
+                        .parse::<i64>()
+                        .map_err(|_| format!("invalid budget \"{}\"", value))?;
+                    result.constraints.budget = Money::from(budget);
+                }
+                OBJECTIVE_ARG => {
+                    let value = iter.next().ok_or("missing value for --objective")?;
+                    result.constraints.objective = match value.as_str() {
+                        "profit" => Objective::Profit,
+                        "roi" => Objective::Roi,
+                        _ => return Err(format!("unknown objective \"{}\"", value)),
+                    };
+                }
+                MAX_BUILDINGS_ARG => {
+                    let value = iter.next().ok_or("missing value for --max-buildings")?;
+                    let max = value
+                        .parse()
+                        .map_err(|_| format!("invalid number of buildings \"{}\"", value))?;
+                    result.constraints.max_buildings = Some(max);
+                }
+                ALLOW_ARG => {
+                    let value = iter.next().ok_or("missing value for --allow")?;
+                    let types = value
+                        .split(',')
+                        .map(|name| {
+                            parse_type(name)
+                                .ok_or_else(|| format!("unknown building type \"{}\"", name))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    result.constraints.allowed = Some(types);
+                }
+                RESEARCHED_ARG => {
+                    let value = iter.next().ok_or("missing value for --researched")?;
+                    let nodes = value
+                        .split(',')
+                        .filter(|name| !name.is_empty())
+                        .map(|name| {
+                            research::tree()
+                                .contains_key(name)
+                                .then(|| name.to_owned())
+                                .ok_or_else(|| format!("unknown research node \"{}\"", name))
+                        })
+                        .collect::<Result<_, _>>()?;
+                    result.constraints.researched = Some(nodes);
+                }
+                RESULTS_ARG => {
+                    let value = iter.next().ok_or("missing value for --results")?;
+                    result.results = value
+                        .parse()
+                        .map_err(|_| format!("invalid number of results \"{}\"", value))?;
+                }
+                SAVE_ARG => {
+                    let value = iter.next().ok_or("missing value for --save")?;
+                    result.save = Some(value.clone());
+                }
+                HORIZON_ARG => {
+                    let value = iter.next().ok_or("missing value for --horizon")?;
+                    let months = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|months| *months > 0.0)
+                        .ok_or_else(|| format!("invalid horizon \"{}\"", value))?;
+                    result.make_or_buy.horizon = months;
+                }
+                RUNS_ARG => {
+                    let value = iter.next().ok_or("missing value for --runs")?;
+                    result.monte_carlo.runs = value
+                        .parse()
+                        .map_err(|_| format!("invalid number of runs \"{}\"", value))?;
+                }
+                SEED_ARG => {
+                    let value = iter.next().ok_or("missing value for --seed")?;
+                    result.monte_carlo.seed = value
+                        .parse()
+                        .map_err(|_| format!("invalid seed \"{}\"", value))?;
+                }
+                PRICE_NOISE_ARG | SPEED_NOISE_ARG => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| format!("missing value for {}", arg))?;
+                    let noise =
+                        parse_noise(value).ok_or_else(|| format!("invalid noise \"{}\"", value))?;
+                    if arg == PRICE_NOISE_ARG {
+                        result.monte_carlo.price = noise;
+                    } else {
+                        result.monte_carlo.speed = noise;
+                    }
+                }
+                ITEMS_ARG => {
+                    let value = iter.next().ok_or("missing value for --items")?;
+                    let items = value
+                        .split(',')
+                        .map(|name| {
+                            parse_item(name).ok_or_else(|| format!("unknown item \"{}\"", name))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    result.items = Some(items);
+                }
+                x if x.starts_with(data::DATA_DIR_ARG) => {}
+                x => result.positional.push(x.to_owned()),
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// 若参数中包含命令行模式的命令则执行它，返回进程退出码；否则返回 `None`，由调用者打开窗口。
+pub fn run(args: &[String]) -> Option<i32> {
+    let args = match Args::parse(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Some(2);
+        }
+    };
+    if args.validate {
+        return Some(run_validate());
+    }
+    match args.positional.first().map(String::as_str) {
+        Some(EVAL_COMMAND) => {
+            let Some(path) = args.positional.get(1) else {
+                eprintln!("usage: eval <plan> [--throttle] [--format table|json|csv]");
+                return Some(2);
+            };
+            Some(print_result(eval(
+                Path::new(path),
+                args.simulation,
+                args.format,
+            )))
+        }
+        Some(TIMELINE_COMMAND) => {
+            let Some(path) = args.positional.get(1) else {
+                eprintln!(
+                    "usage: timeline <plan> [--days <n>] [--no-buy] [--format table|json|csv]"
+                );
+                return Some(2);
+            };
+            Some(print_result(run_timeline(
+                Path::new(path),
+                args.timeline,
+                args.format,
+            )))
+        }
+        Some(SENSITIVITY_COMMAND) => {
+            let Some(path) = args.positional.get(1) else {
+                eprintln!(
+                    "usage: sensitivity <plan> [--range <percent>] [--steps <n>] \
+                     [--items <item,...>] [--throttle] [--format table|json|csv]"
+                );
+                return Some(2);
+            };
+            Some(print_result(run_sensitivity(Path::new(path), &args)))
+        }
+        Some(MONTE_CARLO_COMMAND) => {
+            let Some(path) = args.positional.get(1) else {
+                eprintln!(
+                    "usage: montecarlo <plan> [--runs <n>] [--seed <n>] [--price-noise <noise>] \
+                     [--speed-noise <noise>] [--throttle] [--format table|json|csv]"
+                );
+                return Some(2);
+            };
+            Some(print_result(run_monte_carlo(Path::new(path), &args)))
+        }
+        Some(OPTIMIZE_COMMAND) => Some(print_result(run_optimize(&args))),
+        Some(WAGES_COMMAND) => {
+            let Some(path) = args.positional.get(1) else {
+                eprintln!(
+                    "usage: wages <plan> [--throttle] [--save <plan>] [--format table|json|csv]"
+                );
+                return Some(2);
+            };
+            Some(print_result(run_wages(Path::new(path), &args)))
+        }
+        Some(MAKE_OR_BUY_COMMAND) => {
+            let Some(path) = args.positional.get(1) else {
+                eprintln!("usage: makebuy <plan> [--horizon <months>] [--format table|json|csv]");
+                return Some(2);
+            };
+            Some(print_result(run_make_or_buy(Path::new(path), &args)))
+        }
+        Some(x) => {
+            eprintln!("unknown command \"{}\"", x);
+            Some(2)
+        }
+        None => None,
+    }
+}
+
+/// 格式化为 JSON，末尾加换行。序列化失败时返回错误，而不是输出空内容。
+fn to_json<T: serde::Serialize + ?Sized>(value: &T) -> backend::Result<String> {
+    let mut json = serde_json::to_string_pretty(value).map_err(ErrorImpl::OutputFailed)?;
+    json.push('\n');
+    Ok(json)
+}
+
+fn print_result(result: backend::Result<String>) -> i32 {
+    match result {
+        Ok(output) => {
+            print!("{}", output);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn run_validate() -> i32 {
+    let problems = validate::validate(data::current());
+    for problem in problems.iter() {
+        eprintln!("{}", problem);
+    }
+    eprintln!("{} problem(s) found", problems.len());
+    if problems.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+/// 读取规划文件并模拟，按给定格式输出报告。
+pub fn eval(
+    path: &Path,
+    options: backend::Options,
+    format: OutputFormat,
+) -> backend::Result<String> {
+    let plan = Plan::load(path)?;
+    let report = Simulator::for_plan(&plan)?
+        .with_options(options)
+        .simulate()?;
+    Summary::new(&plan, &report).format(format)
+}
+
+/// 读取规划文件并逐日模拟，按给定格式输出每天的状态。
+pub fn run_timeline(
+    path: &Path,
+    options: timeline::Options,
+    format: OutputFormat,
+) -> backend::Result<String> {
+    let plan = Plan::load(path)?;
+    let timeline = timeline::run(&plan.conditions, &plan.settings, options)?;
+    TimelineSummary::new(&timeline).format(format)
+}
+
+fn run_sensitivity(path: &Path, args: &Args) -> backend::Result<String> {
+    let plan = Plan::load(path)?;
+    let report = Simulator::for_plan(&plan)?
+        .with_options(args.simulation)
+        .simulate()?;
+    let result = sensitivity::sensitivity(
+        &report,
+        args.items.as_deref(),
+        &sensitivity::changes(args.range, args.steps)?,
+    )?;
+    SensitivitySummary::new(&report, &result).format(args.format)
+}
+
+fn run_optimize(args: &Args) -> backend::Result<String> {
+    let constraints = optimizer::Constraints {
+        buy_inputs: !args.simulation.throttle,
+        ..args.constraints.clone()
+    };
+    let solutions = optimizer::optimize(&constraints, args.results)?;
+    let plans = solutions
+        .iter()
+        .enumerate()
+        .map(|(i, solution)| Plan {
+            name: format!("Optimized #{}", i + 1),
+            conditions: solution.conditions.clone(),
+            researched: constraints.researched.clone(),
+            settings: constraints.settings.clone(),
+            market: constraints.market.clone(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    if let (Some(path), Some(best)) = (&args.save, plans.first()) {
+        best.save(Path::new(path))?;
+    }
+    let summaries = plans
+        .iter()
+        .zip(solutions.iter())
+        .map(|(plan, solution)| OptimizedPlan {
+            conditions: plan.conditions.clone(),
+            summary: Summary::new(plan, &solution.report),
+        })
+        .collect::<Vec<_>>();
+    format_optimized(&summaries, args.format)
+}
+
+/// 给出工资建议；指定 `--save` 时把采用全部建议后的规划写入该文件。
+fn run_wages(path: &Path, args: &Args) -> backend::Result<String> {
+    let mut plan = Plan::load(path)?;
+    let advice = wage_advisor::advise(&plan, args.simulation)?;
+    let summary = WageSummary::new(&plan, &advice);
+    if let Some(save) = &args.save {
+        wage_advisor::apply(&mut plan.conditions, &advice);
+        plan.save(Path::new(save))?;
+    }
+    summary.format(args.format)
+}
+
+fn run_make_or_buy(path: &Path, args: &Args) -> backend::Result<String> {
+    let plan = Plan::load(path)?;
+    let rows = make_or_buy::analyze(&plan, args.make_or_buy)?;
+    MakeOrBuySummary::new(&rows).format(args.format)
+}
+
+fn run_monte_carlo(path: &Path, args: &Args) -> backend::Result<String> {
+    let plan = Plan::load(path)?;
+    let report = Simulator::for_plan(&plan)?
+        .with_options(args.simulation)
+        .simulate()?;
+    let outcome = monte_carlo::run(&report, &args.monte_carlo)?;
+    MonteCarloSummary::new(&outcome).format(args.format)
+}
+
+/// 导出用的报告摘要。
+#[derive(Serialize)]
+pub struct Summary {
+    name: String,
+    productivity: BTreeMap<String, f64>,
+    total_buildings: BTreeMap<String, u32>,
+    total_price: i64,
+    monthly_upkeep: i64,
+    monthly_material_cost: i64,
+    monthly_logistics_cost: i64,
+    monthly_sales: i64,
+    monthly_profit: i64,
+    profit_rate: f64,
+    monthly_roi: Option<f64>,
+    payback_months: Option<f64>,
+    /// 启用市场模型时每月卖不出去的剩余物品。
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    unsold: BTreeMap<String, f64>,
+    /// 每月末的累计现金流，第 0 项为 `-total_price`。
+    cash_flow: Vec<i64>,
+    /// 与规划中的条件一一对应。
+    contributions: Vec<ContributionSummary>,
+}
+
+/// 单个条件的贡献摘要。
+#[derive(Serialize)]
+pub struct ContributionSummary {
+    building_type: String,
+    amount: u8,
+    price: i64,
+    monthly_upkeep: i64,
+    monthly_sales: i64,
+    monthly_material_cost: i64,
+    /// 内部流转按市场价结算的净收入。
+    monthly_internal: i64,
+    monthly_logistics: i64,
+    monthly_profit: i64,
+    utilization: f64,
+}
+
+impl Summary {
+    pub fn new(plan: &Plan, report: &Report) -> Summary {
+        Summary {
+            name: plan.name.clone(),
+            productivity: report
+                .productivity()
+                .iter()
+                .map(|(item, speed)| {
+                    (
+                        format!("{:?}", item),
+                        speed.per_month(report.month_length()),
+                    )
+                })
+                .collect(),
+            total_buildings: report
+                .total_buildings()
+                .iter()
+                .map(|(t, amount)| (format!("{:?}", t), *amount))
+                .collect(),
+            total_price: report.total_price().value(),
+            monthly_upkeep: report.monthly_upkeep().value(),
+            monthly_material_cost: report.monthly_material_cost().value(),
+            monthly_logistics_cost: report.monthly_logistics_cost().value(),
+            monthly_sales: report.monthly_sales().value(),
+            monthly_profit: report.monthly_profit().value(),
+            profit_rate: report.profit_rate(),
+            monthly_roi: report.monthly_roi(),
+            payback_months: report.payback_months(),
+            unsold: report
+                .sales()
+                .iter()
+                .filter(|sale| sale.unsold() > balance::TOLERANCE)
+                .map(|sale| (format!("{:?}", sale.item), sale.unsold()))
+                .collect(),
+            cash_flow: report
+                .cash_flow(report.cash_flow_horizon())
+                .into_iter()
+                .map(|money| money.value())
+                .collect(),
+            contributions: plan
+                .conditions
+                .iter()
+                .zip(report.contributions())
+                .map(|(condition, contribution)| ContributionSummary {
+                    building_type: format!("{:?}", condition.building_type()),
+                    amount: condition.amount(),
+                    price: contribution.price().value(),
+                    monthly_upkeep: contribution.upkeep().value(),
+                    monthly_sales: contribution.sales().value(),
+                    monthly_material_cost: contribution.material_cost().value(),
+                    monthly_internal: contribution.internal().value(),
+                    monthly_logistics: contribution.logistics().value(),
+                    monthly_profit: contribution.profit().value(),
+                    utilization: contribution.utilization(),
+                })
+                .collect(),
+        }
+    }
+
+    /// 经济指标，按输出顺序排列。`human` 为真时金额按 `Money` 的格式显示。
+    fn economics(&self, human: bool) -> Vec<(&'static str, String)> {
+        let money = |value: i64| {
+            if human {
+                Money::from(value).to_string()
+            } else {
+                value.to_string()
+            }
+        };
+        vec![
+            ("total_price", money(self.total_price)),
+            ("monthly_upkeep", money(self.monthly_upkeep)),
+            ("monthly_material_cost", money(self.monthly_material_cost)),
+            ("monthly_logistics_cost", money(self.monthly_logistics_cost)),
+            ("monthly_sales", money(self.monthly_sales)),
+            ("monthly_profit", money(self.monthly_profit)),
+            ("profit_rate", format!("{:.4}", self.profit_rate)),
+            (
+                "monthly_roi",
+                self.monthly_roi
+                    .map_or(String::new(), |roi| format!("{:.4}", roi)),
+            ),
+            (
+                "payback_months",
+                self.payback_months
+                    .map_or(String::new(), |months| format!("{:.2}", months)),
+            ),
+        ]
+    }
+
+    pub fn format(&self, format: OutputFormat) -> backend::Result<String> {
+        match format {
+            OutputFormat::Table => Ok(self.to_table()),
+            OutputFormat::Json => to_json(self),
+            OutputFormat::Csv => Ok(self.to_csv()),
+        }
+    }
+
+    fn to_table(&self) -> String {
+        let mut out = String::new();
+        if !self.name.is_empty() {
+            let _ = writeln!(out, "{}\n", self.name);
+        }
+        let _ = writeln!(out, "Productivity (per month)");
+        for (item, monthly) in self.productivity.iter() {
+            let _ = writeln!(out, "  {:<24}{:>12.2}", item, monthly);
+        }
+        let _ = writeln!(out, "Total buildings");
+        for (t, amount) in self.total_buildings.iter() {
+            let _ = writeln!(out, "  {:<40}{:>6}", t, amount);
+        }
+        let _ = writeln!(out, "Economics");
+        for (key, value) in self.economics(true) {
+            let _ = writeln!(out, "  {:<24}{:>12}", key, value);
+        }
+        if !self.unsold.is_empty() {
+            let _ = writeln!(out, "Unsold (per month)");
+            for (item, monthly) in self.unsold.iter() {
+                let _ = writeln!(out, "  {:<24}{:>12.2}", item, monthly);
+            }
+        }
+        let _ = writeln!(out, "Contributions (per month)");
+        for (i, c) in self.contributions.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "  #{:<3}{:<36}x{:<4} price {:>10}  upkeep {:>10}  sales {:>10}  material {:>10}  internal {:>10}  logistics {:>10}  profit {:>10}  busy {:>5.1}%",
+                i + 1,
+                c.building_type,
+                c.amount,
+                Money::from(c.price),
+                Money::from(c.monthly_upkeep),
+                Money::from(c.monthly_sales),
+                Money::from(c.monthly_material_cost),
+                Money::from(c.monthly_internal),
+                Money::from(c.monthly_logistics),
+                Money::from(c.monthly_profit),
+                c.utilization * 100.0,
+            );
+        }
+        let _ = writeln!(out, "Cumulative cash flow");
+        for (month, value) in self.cash_flow.iter().enumerate() {
+            let _ = writeln!(out, "  month {:<18}{:>12}", month, Money::from(*value));
+        }
+        out
+    }
+
+    fn to_csv(&self) -> String {
+        let mut out = String::from("section,key,value\n");
+        for (item, monthly) in self.productivity.iter() {
+            let _ = writeln!(out, "productivity,{},{:.4}", item, monthly);
+        }
+        for (t, amount) in self.total_buildings.iter() {
+            let _ = writeln!(out, "buildings,\"{}\",{}", t, amount);
+        }
+        for (key, value) in self.economics(false) {
+            let _ = writeln!(out, "economics,{},{}", key, value);
+        }
+        for (item, monthly) in self.unsold.iter() {
+            let _ = writeln!(out, "unsold,{},{:.4}", item, monthly);
+        }
+        for (month, value) in self.cash_flow.iter().enumerate() {
+            let _ = writeln!(out, "cash_flow,{},{}", month, value);
+        }
+        for (i, c) in self.contributions.iter().enumerate() {
+            let row = i + 1;
+            let _ = writeln!(
+                out,
+                "contribution_{},building_type,\"{}\"",
+                row, c.building_type
+            );
+            let _ = writeln!(out, "contribution_{},amount,{}", row, c.amount);
+            let _ = writeln!(out, "contribution_{},price,{}", row, c.price);
+            let _ = writeln!(
+                out,
+                "contribution_{},monthly_upkeep,{}",
+                row, c.monthly_upkeep
+            );
+            let _ = writeln!(
+                out,
+                "contribution_{},monthly_sales,{}",
+                row, c.monthly_sales
+            );
+            let _ = writeln!(
+                out,
+                "contribution_{},monthly_material_cost,{}",
+                row, c.monthly_material_cost
+            );
+            let _ = writeln!(
+                out,
+                "contribution_{},monthly_internal,{}",
+                row, c.monthly_internal
+            );
+            let _ = writeln!(
+                out,
+                "contribution_{},monthly_logistics,{}",
+                row, c.monthly_logistics
+            );
+            let _ = writeln!(
+                out,
+                "contribution_{},monthly_profit,{}",
+                row, c.monthly_profit
+            );
+            let _ = writeln!(out, "contribution_{},utilization,{:.4}", row, c.utilization);
+        }
+        out
+    }
+}
+
+/// 导出用的逐日模拟摘要。
+#[derive(Serialize)]
+pub struct TimelineSummary {
+    break_even_day: Option<u32>,
+    /// 与规划中的条件一一对应。
+    utilization: Vec<f64>,
+    days: Vec<DaySummary>,
+}
+
+#[derive(Serialize)]
+pub struct DaySummary {
+    day: u32,
+    cash: i64,
+    starved: u32,
+    inventory: BTreeMap<String, u64>,
+    produced: BTreeMap<String, u64>,
+    bought: BTreeMap<String, u64>,
+    sold: BTreeMap<String, u64>,
+}
+
+fn item_names(map: &BTreeMap<Item, u64>) -> BTreeMap<String, u64> {
+    map.iter()
+        .map(|(item, amount)| (format!("{:?}", item), *amount))
+        .collect()
+}
+
+impl TimelineSummary {
+    pub fn new(timeline: &timeline::Timeline) -> TimelineSummary {
+        TimelineSummary {
+            break_even_day: timeline.break_even_day(),
+            utilization: timeline.utilization().to_vec(),
+            days: timeline
+                .days()
+                .iter()
+                .map(|day| DaySummary {
+                    day: day.day,
+                    cash: day.cash.value(),
+                    starved: day.starved,
+                    inventory: item_names(&day.inventory),
+                    produced: item_names(&day.produced),
+                    bought: item_names(&day.bought),
+                    sold: item_names(&day.sold),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn format(&self, format: OutputFormat) -> backend::Result<String> {
+        match format {
+            OutputFormat::Table => Ok(self.to_table()),
+            OutputFormat::Json => to_json(self),
+            OutputFormat::Csv => Ok(self.to_csv()),
+        }
+    }
+
+    fn to_table(&self) -> String {
+        let join = |map: &BTreeMap<String, u64>| {
+            map.iter()
+                .map(|(item, amount)| format!("{} {}", item, amount))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:>5}  {:>12}  {:>7}  inventory",
+            "day", "cash", "starved"
+        );
+        for day in self.days.iter() {
+            let _ = writeln!(
+                out,
+                "{:>5}  {:>12}  {:>7}  {}",
+                day.day,
+                Money::from(day.cash),
+                day.starved,
+                join(&day.inventory)
+            );
+        }
+        let _ = writeln!(out, "Utilization");
+        for (i, utilization) in self.utilization.iter().enumerate() {
+            let _ = writeln!(out, "  #{:<22}{:>11.1}%", i + 1, utilization * 100.0);
+        }
+        let _ = match self.break_even_day {
+            Some(day) => writeln!(out, "Break even on day {}", day),
+            None => writeln!(out, "Does not break even"),
+        };
+        out
+    }
+
+    fn to_csv(&self) -> String {
+        let mut out = String::from("day,section,key,value\n");
+        for day in self.days.iter() {
+            let _ = writeln!(out, "{},cash,,{}", day.day, day.cash);
+            let _ = writeln!(out, "{},starved,,{}", day.day, day.starved);
+            for (section, map) in [
+                ("inventory", &day.inventory),
+                ("produced", &day.produced),
+                ("bought", &day.bought),
+                ("sold", &day.sold),
+            ] {
+                for (item, amount) in map.iter() {
+                    let _ = writeln!(out, "{},{},{},{}", day.day, section, item, amount);
+                }
+            }
+        }
+        out
+    }
+}
+
+/// 导出用的价格敏感性摘要，按利润变化幅度从大到小排列。
+#[derive(Serialize)]
+pub struct SensitivitySummary {
+    monthly_profit: i64,
+    items: Vec<ItemSensitivity>,
+}
+
+#[derive(Serialize)]
+pub struct ItemSensitivity {
+    item: String,
+    price: i64,
+    net_monthly: f64,
+    points: Vec<SensitivityPoint>,
+}
+
+#[derive(Serialize)]
+pub struct SensitivityPoint {
+    change: f64,
+    monthly_profit: i64,
+    profit_rate: f64,
+}
+
+impl SensitivitySummary {
+    pub fn new(report: &Report, result: &[sensitivity::Sensitivity]) -> SensitivitySummary {
+        SensitivitySummary {
+            monthly_profit: report.monthly_profit().value(),
+            items: result
+                .iter()
+                .map(|s| ItemSensitivity {
+                    item: format!("{:?}", s.item),
+                    price: s.price.value(),
+                    net_monthly: s.net_monthly,
+                    points: s
+                        .points
+                        .iter()
+                        .map(|p| SensitivityPoint {
+                            change: p.change,
+                            monthly_profit: p.monthly_profit.value(),
+                            profit_rate: p.profit_rate,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn format(&self, format: OutputFormat) -> backend::Result<String> {
+        match format {
+            OutputFormat::Table => Ok(self.to_table()),
+            OutputFormat::Json => to_json(self),
+            OutputFormat::Csv => Ok(self.to_csv()),
+        }
+    }
+
+    /// 龙卷风图：每行的条形表示价格在范围两端时利润相对当前利润的变化。
+    fn to_table(&self) -> String {
+        const HALF_WIDTH: f64 = 20.0;
+        let base = self.monthly_profit;
+        let max_swing = self
+            .items
+            .iter()
+            .flat_map(|s| s.points.iter())
+            .map(|p| (p.monthly_profit - base).abs())
+            .max()
+            .unwrap_or(0)
+            .max(1) as f64;
+        let mut out = String::new();
+        let _ = writeln!(out, "Monthly profit {}", Money::from(base));
+        let _ = writeln!(
+            out,
+            "{:<24}{:>12}{:>12}  {:^41}",
+            "item", "low", "high", "profit change"
+        );
+        for s in self.items.iter() {
+            let (Some(low), Some(high)) = (s.points.first(), s.points.last()) else {
+                continue;
+            };
+            let bar = |delta: i64| (delta.abs() as f64 / max_swing * HALF_WIDTH).round() as usize;
+            let (left, right) = {
+                let (a, b) = (low.monthly_profit - base, high.monthly_profit - base);
+                (bar(a.min(b).min(0)), bar(a.max(b).max(0)))
+            };
+            let _ = writeln!(
+                out,
+                "{:<24}{:>12}{:>12}  {:>20}|{:<20}",
+                s.item,
+                Money::from(low.monthly_profit),
+                Money::from(high.monthly_profit),
+                "#".repeat(left),
+                "#".repeat(right)
+            );
+        }
+        out
+    }
+
+    fn to_csv(&self) -> String {
+        let mut out = String::from("item,change,monthly_profit,profit_rate\n");
+        for s in self.items.iter() {
+            for p in s.points.iter() {
+                let _ = writeln!(
+                    out,
+                    "{},{:.4},{},{:.4}",
+                    s.item, p.change, p.monthly_profit, p.profit_rate
+                );
+            }
+        }
+        out
+    }
+}
+
+/// 导出用的优化结果。
+#[derive(Serialize)]
+pub struct OptimizedPlan {
+    conditions: Vec<backend::Condition>,
+    summary: Summary,
+}
+
+fn format_optimized(plans: &[OptimizedPlan], format: OutputFormat) -> backend::Result<String> {
+    Ok(match format {
+        OutputFormat::Table => {
+            let mut out = String::new();
+            for (i, plan) in plans.iter().enumerate() {
+                let _ = writeln!(out, "#{}", i + 1);
+                for cond in plan.conditions.iter() {
+                    let _ = writeln!(
+                        out,
+                        "  {:<32}{:<20}{:>6}  x{}",
+                        format!("{:?}", cond.building_type()),
+                        cond.recipe_id().map_or(String::new(), |id| id.to_string()),
+                        cond.worker_wage().map_or(String::new(), |w| w.to_string()),
+                        cond.amount()
+                    );
+                }
+                for (key, value) in plan.summary.economics(true) {
+                    let _ = writeln!(out, "  {:<24}{:>12}", key, value);
+                }
+            }
+            out
+        }
+        OutputFormat::Json => to_json(plans)?,
+        OutputFormat::Csv => {
+            let mut out = String::from("plan,key,value\n");
+            for (i, plan) in plans.iter().enumerate() {
+                for cond in plan.conditions.iter() {
+                    let _ = writeln!(
+                        out,
+                        "{},\"{:?} {} {}\",{}",
+                        i + 1,
+                        cond.building_type(),
+                        cond.recipe_id().map_or(String::new(), |id| id.to_string()),
+                        cond.worker_wage().map_or(String::new(), |w| w.to_string()),
+                        cond.amount()
+                    );
+                }
+                for (key, value) in plan.summary.economics(false) {
+                    let _ = writeln!(out, "{},{},{}", i + 1, key, value);
+                }
+            }
+            out
+        }
+    })
+}
+
+/// 导出用的工资建议，规划中每个有工人的条件一项。
+#[derive(Serialize)]
+pub struct WageSummary {
+    rows: Vec<WageRow>,
+}
+
+#[derive(Serialize)]
+pub struct WageRow {
+    building_type: String,
+    recipe: String,
+    current_wage: u16,
+    recommended_wage: u16,
+    current_monthly_profit: i64,
+    recommended_monthly_profit: i64,
+    /// 改用建议工资后规划月利润的增加。
+    plan_gain: i64,
+}
+
+impl WageSummary {
+    pub fn new(plan: &Plan, advice: &[wage_advisor::Advice]) -> WageSummary {
+        WageSummary {
+            rows: advice
+                .iter()
+                .map(|a| {
+                    let cond = &plan.conditions[a.index];
+                    WageRow {
+                        building_type: format!("{:?}", cond.building_type()),
+                        recipe: cond.recipe_id().map_or(String::new(), |id| id.to_string()),
+                        current_wage: a.current.percent(),
+                        recommended_wage: a.best.percent(),
+                        current_monthly_profit: a.current_profit.value(),
+                        recommended_monthly_profit: a.best_profit.value(),
+                        plan_gain: a.gain.value(),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn format(&self, format: OutputFormat) -> backend::Result<String> {
+        match format {
+            OutputFormat::Table => Ok(self.to_table()),
+            OutputFormat::Json => to_json(self),
+            OutputFormat::Csv => Ok(self.to_csv()),
+        }
+    }
+
+    fn to_table(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:<32}{:<20}{:>8}{:>12}{:>12}{:>12}{:>12}",
+            "building", "recipe", "wage", "advised", "profit", "advised", "gain"
+        );
+        for row in self.rows.iter() {
+            let _ = writeln!(
+                out,
+                "{:<32}{:<20}{:>7}%{:>11}%{:>12}{:>12}{:>12}",
+                row.building_type,
+                row.recipe,
+                row.current_wage,
+                row.recommended_wage,
+                Money::from(row.current_monthly_profit),
+                Money::from(row.recommended_monthly_profit),
+                Money::from(row.plan_gain)
+            );
+        }
+        out
+    }
+
+    fn to_csv(&self) -> String {
+        let mut out = String::from(
+            "building_type,recipe,current_wage,recommended_wage,current_monthly_profit,\
+             recommended_monthly_profit,plan_gain\n",
+        );
+        for row in self.rows.iter() {
+            let _ = writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                row.building_type,
+                row.recipe,
+                row.current_wage,
+                row.recommended_wage,
+                row.current_monthly_profit,
+                row.recommended_monthly_profit,
+                row.plan_gain
+            );
+        }
+        out
+    }
+}
+
+/// 导出用的自制或外购分析，规划消耗的每种物品一项。
+#[derive(Serialize)]
+pub struct MakeOrBuySummary {
+    rows: Vec<MakeOrBuyRow>,
+}
+
+#[derive(Serialize)]
+pub struct MakeOrBuyRow {
+    item: String,
+    monthly_consumed: f64,
+    monthly_produced: f64,
+    /// 单位市场价，没有价格时为空。
+    buy_cost: Option<f64>,
+    /// 自制的单位成本，造不出时为空。
+    make_cost: Option<f64>,
+    building_type: Option<String>,
+    recipe: Option<String>,
+    verdict: String,
+    monthly_saving: Option<f64>,
+}
+
+impl MakeOrBuySummary {
+    pub fn new(rows: &[make_or_buy::Row]) -> MakeOrBuySummary {
+        MakeOrBuySummary {
+            rows: rows
+                .iter()
+                .map(|row| MakeOrBuyRow {
+                    item: format!("{:?}", row.item),
+                    monthly_consumed: row.consumed,
+                    monthly_produced: row.produced,
+                    buy_cost: row.buy,
+                    make_cost: row.make.as_ref().map(|make| make.unit_cost()),
+                    building_type: row
+                        .make
+                        .as_ref()
+                        .map(|make| format!("{:?}", make.building_type)),
+                    recipe: row.make.as_ref().map(|make| make.recipe_id.to_string()),
+                    verdict: format!("{:?}", row.verdict()).to_lowercase(),
+                    monthly_saving: row.monthly_saving(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn format(&self, format: OutputFormat) -> backend::Result<String> {
+        match format {
+            OutputFormat::Table => Ok(self.to_table()),
+            OutputFormat::Json => to_json(self),
+            OutputFormat::Csv => Ok(self.to_csv()),
+        }
+    }
+
+    fn to_table(&self) -> String {
+        let cost = |x: Option<f64>| x.map_or(String::from("-"), |x| format!("{:.2}", x));
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:<24}{:>12}{:>12}{:>12}{:>12}  {:<12}{:>12}  made by",
+            "item", "consumed", "produced", "buy", "make", "verdict", "saving"
+        );
+        for row in self.rows.iter() {
+            let _ = writeln!(
+                out,
+                "{:<24}{:>12.2}{:>12.2}{:>12}{:>12}  {:<12}{:>12}  {}",
+                row.item,
+                row.monthly_consumed,
+                row.monthly_produced,
+                cost(row.buy_cost),
+                cost(row.make_cost),
+                row.verdict,
+                cost(row.monthly_saving),
+                match (&row.building_type, &row.recipe) {
+                    (Some(building_type), Some(recipe)) => format!("{} {}", building_type, recipe),
+                    _ => String::new(),
+                }
+            );
+        }
+        out
+    }
+
+    fn to_csv(&self) -> String {
+        let cost = |x: Option<f64>| x.map_or(String::new(), |x| x.to_string());
+        let mut out = String::from(
+            "item,monthly_consumed,monthly_produced,buy_cost,make_cost,building_type,recipe,\
+             verdict,monthly_saving\n",
+        );
+        for row in self.rows.iter() {
+            let _ = writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{}",
+                row.item,
+                row.monthly_consumed,
+                row.monthly_produced,
+                cost(row.buy_cost),
+                cost(row.make_cost),
+                row.building_type.clone().unwrap_or_default(),
+                row.recipe.clone().unwrap_or_default(),
+                row.verdict,
+                cost(row.monthly_saving)
+            );
+        }
+        out
+    }
+}
+
+/// 报告中的分位数。
+const PERCENTILES: [f64; 5] = [0.05, 0.25, 0.5, 0.75, 0.95];
+
+/// 导出用的蒙特卡洛模拟摘要。
+#[derive(Serialize)]
+pub struct MonteCarloSummary {
+    runs: usize,
+    mean_monthly_profit: i64,
+    loss_probability: f64,
+    /// 百分位到月利润。
+    monthly_profit: BTreeMap<String, i64>,
+    /// 百分位到回本月数，不盈利时为空。
+    payback_months: BTreeMap<String, Option<f64>>,
+}
+
+impl MonteCarloSummary {
+    pub fn new(outcome: &monte_carlo::Outcome) -> MonteCarloSummary {
+        let key = |p: f64| format!("p{:02.0}", p * 100.0);
+        MonteCarloSummary {
+            runs: outcome.runs(),
+            mean_monthly_profit: outcome.mean_profit().value(),
+            loss_probability: outcome.loss_probability(),
+            monthly_profit: PERCENTILES
+                .iter()
+                .map(|p| (key(*p), outcome.profit_percentile(*p).value()))
+                .collect(),
+            payback_months: PERCENTILES
+                .iter()
+                .map(|p| (key(*p), outcome.payback_percentile(*p)))
+                .collect(),
+        }
+    }
+
+    pub fn format(&self, format: OutputFormat) -> backend::Result<String> {
+        match format {
+            OutputFormat::Table => Ok(self.to_table()),
+            OutputFormat::Json => to_json(self),
+            OutputFormat::Csv => Ok(self.to_csv()),
+        }
+    }
+
+    fn to_table(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{:<24}{:>12}", "runs", self.runs);
+        let _ = writeln!(
+            out,
+            "{:<24}{:>12}",
+            "mean monthly profit",
+            Money::from(self.mean_monthly_profit)
+        );
+        let _ = writeln!(
+            out,
+            "{:<24}{:>11.1}%",
+            "probability of loss",
+            self.loss_probability * 100.0
+        );
+        let _ = writeln!(out, "Monthly profit");
+        for (key, profit) in self.monthly_profit.iter() {
+            let _ = writeln!(out, "  {:<22}{:>12}", key, Money::from(*profit));
+        }
+        let _ = writeln!(out, "Payback months");
+        for (key, months) in self.payback_months.iter() {
+            let months = months.map_or(String::from("never"), |months| format!("{:.1}", months));
+            let _ = writeln!(out, "  {:<22}{:>12}", key, months);
+        }
+        out
+    }
+
+    fn to_csv(&self) -> String {
+        let mut out = String::from("section,key,value\n");
+        let _ = writeln!(out, "summary,runs,{}", self.runs);
+        let _ = writeln!(
+            out,
+            "summary,mean_monthly_profit,{}",
+            self.mean_monthly_profit
+        );
+        let _ = writeln!(out, "summary,loss_probability,{:.4}", self.loss_probability);
+        for (key, profit) in self.monthly_profit.iter() {
+            let _ = writeln!(out, "monthly_profit,{},{}", key, profit);
+        }
+        for (key, months) in self.payback_months.iter() {
+            let months = months.map_or(String::new(), |months| format!("{:.2}", months));
+            let _ = writeln!(out, "payback_months,{},{}", key, months);
+        }
+        out
+    }
+}
+
+#[test]
+fn summary_test() {
+    use crate::backend::{building, recipe::Id, Condition};
+    let plan = Plan {
+        conditions: vec![Condition::Collector {
+            building_type: building::types::Collector::Lumberyard,
+            recipe_id: Id(String::from("Wood")),
+            worker_wage: building::WorkerWage::PERCENT_100,
+            collector_amount: building::OutbuildingAmount::One,
+            amount: 1,
+            location: None,
+        }],
+        ..Default::default()
+    };
+    let report = Simulator::from_conditions(&plan.conditions)
+        .unwrap()
+        .simulate()
+        .unwrap();
+    let summary = Summary::new(&plan, &report);
+    let csv = summary.format(OutputFormat::Csv).unwrap();
+    assert!(csv.contains("productivity,Wood,2.0000"));
+    assert!(csv.contains("economics,total_price,200000"));
+    let json: serde_json::Value =
+        serde_json::from_str(&summary.format(OutputFormat::Json).unwrap()).unwrap();
+    assert_eq!(json["total_price"], 200000);
+    assert_eq!(json["cash_flow"][0], -200000);
+    assert!(csv.contains("cash_flow,0,-200000"));
+    assert!(csv.contains("contribution_1,price,200000"));
+    assert!(csv.contains("contribution_1,monthly_internal,0"));
+    assert!(csv.contains("contribution_1,utilization,1.0000"));
+    assert_eq!(json["contributions"][0]["amount"], 1);
+}
+
+#[test]
+fn parse_noise_test() {
+    assert_eq!(parse_noise("uniform:20"), Some(Noise::Uniform(0.2)));
+    assert_eq!(parse_noise("normal:0"), Some(Noise::Normal(0.0)));
+    assert_eq!(parse_noise("normal:inf"), None);
+    assert_eq!(parse_noise("uniform:NaN"), None);
+    assert_eq!(parse_noise("uniform:-5"), None);
+}
@@ -3,10 +3,31 @@ extern crate lazy_static_include;
 
 mod app;
 mod backend;
+mod cli;
 
-const APP_NAME: &'static str = "ROI Calculator";
+use backend::data;
+
+const APP_NAME: &str = "ROI Calculator";
 
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let data_dir = data::locate(args.iter().cloned());
+    let watcher = match &data_dir {
+        Some(dir) => match data::GameData::load_dir(dir) {
+            Ok(game_data) => {
+                data::install(game_data);
+                Some(data::Watcher::new(dir.clone()))
+            }
+            Err(e) => {
+                eprintln!("failed to load data from {}: {}", dir.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
     let options = eframe::NativeOptions {
         default_theme: eframe::Theme::Light,
         ..Default::default()
@@ -15,7 +36,7 @@ fn main() {
         APP_NAME,
         options,
         Box::new(|cc| {
-            let mut app = app::App::new(cc);
+            let mut app = app::App::new(cc, watcher);
             app.simulate();
             Box::new(app)
         }),